@@ -0,0 +1,44 @@
+use std::{
+  sync::{Arc, Mutex, PoisonError},
+  time::Instant,
+};
+use tokio::sync::Notify;
+
+/// Lets auxiliary tasks tell the monitor that the user is active right
+/// now, e.g. because of input the idle source doesn't see. Cheap to clone.
+#[derive(Clone, Default)]
+pub struct Activity {
+  inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+  notify: Notify,
+  last: Mutex<Option<Instant>>,
+}
+
+impl Activity {
+  /// Treat the user as active from now on, pushing the hint right away.
+  pub fn reset_activity(&self) {
+    *self.lock() = Some(Instant::now());
+    self.inner.notify.notify_one();
+  }
+
+  /// When activity was last reported, if ever.
+  pub fn last(&self) -> Option<Instant> {
+    *self.lock()
+  }
+
+  /// Wait for the next report.
+  pub async fn notified(&self) {
+    self.inner.notify.notified().await
+  }
+
+  fn lock(&self) -> std::sync::MutexGuard<'_, Option<Instant>> {
+    self
+      .inner
+      .last
+      .lock()
+      .unwrap_or_else(PoisonError::into_inner)
+  }
+}
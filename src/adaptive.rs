@@ -0,0 +1,193 @@
+use std::{
+  fmt,
+  str::FromStr,
+  time::{Duration, Instant},
+};
+
+use crate::monitor::Thresholds;
+
+/// Weight of the latest stretch in the running averages.
+const SMOOTHING: f64 = 0.25;
+
+/// Bounds of the adaptive idle threshold, e.g. `120-1800` in seconds.
+#[derive(Clone, Copy, Debug)]
+pub struct Band {
+  min: Duration,
+  max: Duration,
+}
+
+impl Band {
+  pub fn min(&self) -> Duration {
+    self.min
+  }
+
+  pub fn max(&self) -> Duration {
+    self.max
+  }
+}
+
+impl FromStr for Band {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let parse = |secs: &str| {
+      secs
+        .trim()
+        .parse()
+        .map(Duration::from_secs)
+        .map_err(|e| format!("invalid number of seconds '{secs}': {e}"))
+    };
+
+    let (min, max) = s
+      .split_once('-')
+      .ok_or_else(|| format!("invalid band '{s}', expected MIN-MAX"))?;
+    let (min, max) = (parse(min)?, parse(max)?);
+    if min.is_zero() || min > max {
+      return Err(format!(
+        "invalid band '{s}', expected 0 < MIN <= MAX seconds"
+      ));
+    }
+
+    Ok(Self { min, max })
+  }
+}
+
+impl fmt::Display for Band {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}-{}", self.min.as_secs(), self.max.as_secs())
+  }
+}
+
+/// Tunes the idle threshold to the user's habits. It is the geometric mean
+/// of the average active stretch and the average idle episode, so that it
+/// grows while the user stays active for long and shrinks after repeated
+/// short breaks, within a band.
+pub struct AdaptiveThreshold {
+  band: Band,
+  /// The configured thresholds, whose hysteresis is kept
+  base: Thresholds,
+  /// Running averages of active stretches and idle episodes, in seconds
+  active: f64,
+  idle: f64,
+  since: Instant,
+}
+
+impl AdaptiveThreshold {
+  pub fn new(band: Band, base: Thresholds) -> Self {
+    // Both averages start out at the configured threshold
+    let secs = base.enter.as_secs_f64();
+    Self {
+      band,
+      base,
+      active: secs,
+      idle: secs,
+      since: Instant::now(),
+    }
+  }
+
+  /// Account for the user becoming `idle` or active, which ends a stretch
+  /// of the other state.
+  pub fn transition(&mut self, idle: bool) {
+    let elapsed = self.since.elapsed();
+    self.since = Instant::now();
+    self.record(idle, elapsed);
+  }
+
+  /// Fold a stretch of `elapsed` that ended with the user becoming `idle`
+  /// or active into the averages.
+  fn record(&mut self, idle: bool, elapsed: Duration) {
+    let average = if idle {
+      &mut self.active
+    } else {
+      &mut self.idle
+    };
+    *average += SMOOTHING * (elapsed.as_secs_f64() - *average);
+  }
+
+  /// The thresholds to use at this point.
+  pub fn thresholds(&self) -> Thresholds {
+    let enter = Duration::from_secs_f64((self.active * self.idle).sqrt())
+      .clamp(self.band.min, self.band.max);
+    let ratio = if self.base.enter.is_zero() {
+      1.0
+    } else {
+      self.base.exit.as_secs_f64() / self.base.enter.as_secs_f64()
+    };
+    Thresholds {
+      enter,
+      exit: enter.mul_f64(ratio),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn adaptive(band: &str, enter: u64, exit: u64) -> AdaptiveThreshold {
+    let base = Thresholds {
+      enter: Duration::from_secs(enter),
+      exit: Duration::from_secs(exit),
+    };
+    AdaptiveThreshold::new(band.parse().unwrap(), base)
+  }
+
+  /// Alternate active stretches and idle episodes of the given lengths.
+  fn cycle(adaptive: &mut AdaptiveThreshold, active: u64, idle: u64) {
+    adaptive.record(true, Duration::from_secs(active));
+    adaptive.record(false, Duration::from_secs(idle));
+  }
+
+  #[test]
+  fn parses_bands() {
+    let band: Band = "120-1800".parse().unwrap();
+    assert_eq!((band.min().as_secs(), band.max().as_secs()), (120, 1800));
+    assert_eq!(band.to_string(), "120-1800");
+    assert!("0-1800".parse::<Band>().is_err());
+    assert!("1800-120".parse::<Band>().is_err());
+    assert!("120".parse::<Band>().is_err());
+  }
+
+  #[test]
+  fn starts_at_configured_threshold() {
+    let adaptive = adaptive("120-1800", 300, 240);
+    let thresholds = adaptive.thresholds();
+    assert_eq!(thresholds.enter, Duration::from_secs(300));
+    assert_eq!(thresholds.exit, Duration::from_secs(240));
+  }
+
+  #[test]
+  fn stays_within_band() {
+    let mut adaptive = adaptive("120-1800", 300, 300);
+    for _ in 0..50 {
+      cycle(&mut adaptive, 36_000, 36_000);
+      assert!(adaptive.thresholds().enter <= Duration::from_secs(1800));
+    }
+    assert_eq!(adaptive.thresholds().enter, Duration::from_secs(1800));
+
+    for _ in 0..50 {
+      cycle(&mut adaptive, 1, 1);
+      assert!(adaptive.thresholds().enter >= Duration::from_secs(120));
+    }
+    assert_eq!(adaptive.thresholds().enter, Duration::from_secs(120));
+  }
+
+  #[test]
+  fn converges_to_geometric_mean() {
+    let mut adaptive = adaptive("60-3600", 300, 150);
+    for _ in 0..50 {
+      cycle(&mut adaptive, 1600, 900);
+    }
+    // sqrt(1600 * 900) == 1200
+    let thresholds = adaptive.thresholds();
+    assert!(
+      thresholds
+        .enter
+        .abs_diff(Duration::from_secs(1200))
+        .as_secs()
+        < 1
+    );
+    // The hysteresis of the configured thresholds is kept
+    assert_eq!(thresholds.exit, thresholds.enter / 2);
+  }
+}
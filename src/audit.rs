@@ -0,0 +1,98 @@
+use anyhow::{Context, Result};
+use chrono::{Local, SecondsFormat};
+use serde::Serialize;
+use std::{
+  fs::{File, OpenOptions},
+  io::Write,
+  path::{Path, PathBuf},
+  sync::{Arc, Mutex, PoisonError},
+  time::Duration,
+};
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{error, info};
+
+use crate::monitor::Reason;
+
+#[derive(Serialize)]
+struct Record {
+  time: String,
+  state: &'static str,
+  idle_ms: u64,
+  reason: String,
+}
+
+/// Append-only record of idle state transitions, one JSON object per line.
+/// Every record is synced to disk before moving on.
+#[derive(Clone, Debug)]
+pub struct AuditLog {
+  path: PathBuf,
+  file: Arc<Mutex<File>>,
+}
+
+impl AuditLog {
+  pub fn open(path: &Path) -> Result<Self> {
+    Ok(Self {
+      path: path.to_path_buf(),
+      file: Arc::new(Mutex::new(open_audit_file(path)?)),
+    })
+  }
+
+  /// Append a record of the user becoming idle or active. Failures are
+  /// logged, as they shouldn't take down the monitor.
+  pub fn record(&self, idle: bool, idle_time: Duration, reason: Reason) {
+    let record = Record {
+      time: Local::now().to_rfc3339_opts(SecondsFormat::Millis, false),
+      state: if idle { "idle" } else { "active" },
+      idle_ms: idle_time.as_millis() as u64,
+      reason: reason.to_string(),
+    };
+    let line = match serde_json::to_string(&record) {
+      Ok(line) => line,
+      Err(e) => {
+        error!("Failed to serialize audit record: {e}");
+        return;
+      }
+    };
+
+    let mut file = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+    let result = writeln!(file, "{line}").and_then(|()| file.sync_data());
+    if let Err(e) = result {
+      error!(
+        "Failed to write audit record to {}: {e}",
+        self.path.display()
+      );
+    }
+  }
+
+  /// Reopen the audit file whenever SIGHUP is received, so that it can be
+  /// rotated like the log file.
+  pub fn reopen_on_sighup(&self) -> Result<()> {
+    let mut sighup = signal(SignalKind::hangup())
+      .context("Failed to install SIGHUP handler")?;
+    let audit = self.clone();
+
+    tokio::spawn(async move {
+      while sighup.recv().await.is_some() {
+        match open_audit_file(&audit.path) {
+          Ok(new_file) => {
+            let mut file =
+              audit.file.lock().unwrap_or_else(PoisonError::into_inner);
+            *file = new_file;
+            info!("Received SIGHUP, reopened audit file");
+          }
+          Err(e) => error!("{e:#}, still writing to the old audit file"),
+        }
+      }
+    });
+
+    Ok(())
+  }
+}
+
+fn open_audit_file(path: &Path) -> Result<File> {
+  OpenOptions::new()
+    .create(true)
+    .append(true)
+    .open(path)
+    .with_context(|| format!("Failed to open audit file {}", path.display()))
+}
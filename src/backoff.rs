@@ -0,0 +1,155 @@
+use anyhow::Result;
+use std::{future::Future, time::Duration};
+use tokio::time::sleep;
+use tracing::warn;
+
+use crate::exit::Fatal;
+
+/// Tunables shared by all retry sites.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryConfig {
+  pub initial_delay: Duration,
+  pub max_delay: Duration,
+  pub multiplier: f64,
+  /// Total number of attempts, including the first one. `0` retries
+  /// forever.
+  pub max_attempts: u32,
+}
+
+/// Exponential backoff computing the delay before each retry.
+pub struct Backoff {
+  config: RetryConfig,
+  attempts: u32,
+  delay: Duration,
+}
+
+impl Backoff {
+  pub fn new(config: RetryConfig) -> Self {
+    Self {
+      config,
+      attempts: 0,
+      delay: config.initial_delay,
+    }
+  }
+
+  /// Record a failed attempt and return how long to wait before the next
+  /// one, or `None` if no attempts are left.
+  pub fn next_delay(&mut self) -> Option<Duration> {
+    self.attempts = self.attempts.saturating_add(1);
+    if self.config.max_attempts != 0
+      && self.attempts >= self.config.max_attempts
+    {
+      return None;
+    }
+
+    let delay = self.delay.min(self.config.max_delay);
+    self.delay =
+      Duration::try_from_secs_f64(delay.as_secs_f64() * self.config.multiplier)
+        .unwrap_or(self.config.max_delay)
+        .min(self.config.max_delay);
+    Some(delay)
+  }
+}
+
+/// Run `f` until it succeeds or the attempts are exhausted, returning the
+/// last error in the latter case. Configuration errors are returned right
+/// away.
+pub async fn retry<T, F, Fut>(
+  config: RetryConfig,
+  what: &str,
+  mut f: F,
+) -> Result<T>
+where
+  F: FnMut() -> Fut,
+  Fut: Future<Output = Result<T>>,
+{
+  let mut backoff = Backoff::new(config);
+  loop {
+    match f().await {
+      Ok(value) => return Ok(value),
+      // Retrying won't fix the configuration
+      Err(e) if e.downcast_ref() == Some(&Fatal::Config) => return Err(e),
+      Err(e) => match backoff.next_delay() {
+        Some(delay) => {
+          warn!("{what} failed, retrying in {delay:?}: {e:#}");
+          sleep(delay).await;
+        }
+        None => return Err(e),
+      },
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use anyhow::{anyhow, bail};
+
+  fn config(max_attempts: u32) -> RetryConfig {
+    RetryConfig {
+      initial_delay: Duration::from_millis(1),
+      max_delay: Duration::from_millis(5),
+      multiplier: 2.0,
+      max_attempts,
+    }
+  }
+
+  #[test]
+  fn delays_double_up_to_max() {
+    let mut backoff = Backoff::new(config(0));
+    let delays: Vec<_> = (0..6)
+      .map(|_| backoff.next_delay().unwrap().as_millis())
+      .collect();
+    assert_eq!(delays, [1, 2, 4, 5, 5, 5]);
+  }
+
+  #[test]
+  fn attempts_run_out() {
+    let mut backoff = Backoff::new(config(3));
+    assert!(backoff.next_delay().is_some());
+    assert!(backoff.next_delay().is_some());
+    assert_eq!(backoff.next_delay(), None);
+  }
+
+  #[tokio::test]
+  async fn retry_gives_up_after_max_attempts() {
+    let mut attempts = 0;
+    let result: Result<()> = retry(config(3), "Test", || {
+      attempts += 1;
+      async { bail!("failed") }
+    })
+    .await;
+    assert!(result.is_err());
+    assert_eq!(attempts, 3);
+  }
+
+  #[tokio::test]
+  async fn retry_stops_at_config_errors() {
+    let mut attempts = 0;
+    let result: Result<()> = retry(config(5), "Test", || {
+      attempts += 1;
+      async { Err(anyhow!("bad threshold").context(Fatal::Config)) }
+    })
+    .await;
+    assert!(result.is_err());
+    assert_eq!(attempts, 1);
+  }
+
+  #[tokio::test]
+  async fn retry_returns_first_success() {
+    let mut attempts = 0;
+    let result = retry(config(5), "Test", || {
+      attempts += 1;
+      let attempt = attempts;
+      async move {
+        if attempt < 2 {
+          bail!("failed");
+        }
+        Ok(attempt)
+      }
+    })
+    .await;
+    assert_eq!(result.unwrap(), 2);
+    assert_eq!(attempts, 2);
+  }
+}
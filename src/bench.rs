@@ -0,0 +1,173 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+
+use crate::{
+  exit::Fatal,
+  monitor::{self, Config},
+  source::{IdleSource, SourceSpec},
+  x11::{ScreensaverSource, XOptions},
+  ExitSignals,
+};
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BenchFormat {
+  #[default]
+  Text,
+  Json,
+}
+
+/// Statistics over a series of durations, in milliseconds.
+#[derive(Serialize)]
+struct Timing {
+  samples: usize,
+  min_ms: f64,
+  max_ms: f64,
+  mean_ms: f64,
+  p99_ms: f64,
+}
+
+impl Timing {
+  /// `None` without samples to go by.
+  fn new(mut samples: Vec<Duration>) -> Option<Self> {
+    samples.sort();
+    let (min, max) = (*samples.first()?, *samples.last()?);
+    let mean = samples.iter().sum::<Duration>() / samples.len() as u32;
+    // Nearest rank
+    let p99 = samples[(samples.len() * 99).div_ceil(100) - 1];
+    let ms = |duration: Duration| duration.as_secs_f64() * 1000.0;
+    Some(Self {
+      samples: samples.len(),
+      min_ms: ms(min),
+      max_ms: ms(max),
+      mean_ms: ms(mean),
+      p99_ms: ms(p99),
+    })
+  }
+}
+
+#[derive(Serialize)]
+struct Report {
+  duration_s: f64,
+  polls: u64,
+  /// Polls without a usable reading
+  failed_polls: u64,
+  /// Time between successive successful readings
+  interval: Option<Timing>,
+  /// Round trip of a screensaver query, with the screensaver source
+  #[serde(skip_serializing_if = "Option::is_none")]
+  x_reply: Option<Timing>,
+}
+
+impl Report {
+  fn print(&self) {
+    println!(
+      "{} polls in {:.1}s, {} without a reading",
+      self.polls, self.duration_s, self.failed_polls
+    );
+    match &self.interval {
+      Some(interval) => print_timing("Interval between readings", interval),
+      None => println!("Interval between readings: too few readings"),
+    }
+    if let Some(x_reply) = &self.x_reply {
+      print_timing("X reply latency", x_reply);
+    }
+  }
+}
+
+/// Run the poll loop for `duration` without D-Bus, then print how regular
+/// the readings were and how long the X server took to answer.
+pub async fn run(
+  config: &Config,
+  duration: Duration,
+  format: BenchFormat,
+  mut signals: ExitSignals,
+) -> Result<()> {
+  let check_interval = monitor::check_interval(config);
+  let mut source =
+    IdleSource::new(&config.source, check_interval, config.retry).await?;
+
+  // The display tasks own their connections, so time queries on connections
+  // of our own
+  let mut raw_sources = vec![];
+  if config.source.spec == SourceSpec::Screensaver {
+    let displays: Vec<_> = if config.source.displays.is_empty() {
+      vec![None]
+    } else {
+      config.source.displays.iter().map(Some).collect()
+    };
+    let x_options = XOptions {
+      connect_timeout: config.source.x11.connect_timeout,
+      reply_timeout: config.source.x11.reply_timeout,
+      ..Default::default()
+    };
+    for display in displays {
+      let raw_source =
+        ScreensaverSource::connect(display.map(String::as_str), x_options)
+          .await
+          .context(Fatal::XUnavailable)?;
+      raw_sources.push(raw_source);
+    }
+  }
+
+  let started = Instant::now();
+  let deadline = sleep(duration);
+  tokio::pin!(deadline);
+  let mut timer = monitor::poll_timer(config, check_interval);
+  let (mut polls, mut failed_polls) = (0, 0);
+  let mut last_read = None;
+  let (mut intervals, mut replies) = (vec![], vec![]);
+
+  // Like the monitor, poll right away and then on the timer
+  loop {
+    let reading = source.idle_time().await?;
+    if source.ended() {
+      break;
+    }
+    polls += 1;
+    match reading {
+      Some(_) => {
+        let now = Instant::now();
+        if let Some(last) = last_read.replace(now) {
+          intervals.push(now - last);
+        }
+      }
+      None => failed_polls += 1,
+    }
+    for raw_source in &raw_sources {
+      let sent = Instant::now();
+      raw_source.idle_time().await?;
+      replies.push(sent.elapsed());
+    }
+
+    tokio::select! {
+      _ = signals.recv() => break,
+      _ = &mut deadline => break,
+      _ = timer.tick() => {}
+    }
+  }
+
+  let report = Report {
+    duration_s: started.elapsed().as_secs_f64(),
+    polls,
+    failed_polls,
+    interval: Timing::new(intervals),
+    x_reply: Timing::new(replies),
+  };
+  match format {
+    BenchFormat::Json => println!("{}", serde_json::to_string(&report)?),
+    BenchFormat::Text => report.print(),
+  }
+  Ok(())
+}
+
+fn print_timing(name: &str, timing: &Timing) {
+  println!(
+    "{name}: min {:.3}ms, max {:.3}ms, mean {:.3}ms, p99 {:.3}ms ({} \
+     samples)",
+    timing.min_ms, timing.max_ms, timing.mean_ms, timing.p99_ms, timing.samples
+  );
+}
@@ -0,0 +1,386 @@
+use anyhow::{bail, Context, Result};
+use clap::{
+  error::ErrorKind, parser::ValueSource, ArgMatches, CommandFactory,
+  FromArgMatches, ValueEnum,
+};
+use serde::Serialize;
+use std::{
+  collections::BTreeMap,
+  ffi::OsString,
+  fs,
+  path::{Path, PathBuf},
+};
+use toml::{Table, Value};
+
+use crate::{
+  logging::LogLevel,
+  monitor::{self, Config, Mode},
+  state_file::StateFormat,
+  Args,
+};
+
+/// Keys of the config file that aren't command line options.
+const SESSIONS_KEY: &str = "sessions";
+const PROFILES_KEY: &str = "profiles";
+const DEFAULT_PROFILE_KEY: &str = "default-profile";
+
+/// Options that only make sense on the command line.
+const CLI_ONLY: &[&str] = &["config", "profile", "help", "version"];
+
+/// Settings from `--config` that have no command line equivalent.
+#[derive(Default, Debug)]
+pub struct ConfigFile {
+  /// Idle threshold in seconds by logind session id or seat name
+  pub sessions: BTreeMap<String, u64>,
+  /// The profile whose settings were applied, if any
+  pub profile: Option<String>,
+}
+
+/// Parse the command line, filling in options that weren't given from the
+/// `--config` file, if any. Exits on invalid arguments or config.
+pub fn parse_args() -> (Args, ConfigFile) {
+  try_parse_args().unwrap_or_else(|e| match e.downcast::<clap::Error>() {
+    Ok(e) => e.exit(),
+    Err(e) => Args::command()
+      .error(ErrorKind::Io, format!("{e:#}"))
+      .exit(),
+  })
+}
+
+/// Like [`parse_args`], but returns errors instead of exiting, for
+/// re-reading the config file at runtime.
+pub fn try_parse_args() -> Result<(Args, ConfigFile)> {
+  let matches = Args::command().try_get_matches()?;
+  let Some(path) = matches.get_one::<PathBuf>("config") else {
+    return Ok((Args::from_arg_matches(&matches)?, ConfigFile::default()));
+  };
+
+  let (file_args, file) =
+    load(path, &matches).with_context(|| path.display().to_string())?;
+
+  let mut argv: Vec<OsString> = std::env::args_os().take(1).collect();
+  argv.extend(file_args);
+  argv.extend(std::env::args_os().skip(1));
+  let matches = Args::command().try_get_matches_from(argv)?;
+  Ok((Args::from_arg_matches(&matches)?, file))
+}
+
+/// Read the config file at `path`, turning its keys into command line
+/// arguments for the options not already given in `matches`.
+fn load(
+  path: &Path,
+  matches: &ArgMatches,
+) -> Result<(Vec<OsString>, ConfigFile)> {
+  let content = fs::read_to_string(path).context("Failed to read file")?;
+  let mut table: Table = content.parse().context("Failed to parse TOML")?;
+
+  let mut file = ConfigFile {
+    profile: select_profile(&mut table, matches)?,
+    ..ConfigFile::default()
+  };
+  if let Some(sessions) = table.remove(SESSIONS_KEY) {
+    let Value::Table(sessions) = sessions else {
+      bail!("'{SESSIONS_KEY}' must be a table");
+    };
+    for (name, threshold) in sessions {
+      let threshold = threshold
+        .as_integer()
+        .and_then(|threshold| u64::try_from(threshold).ok())
+        .with_context(|| {
+          format!("Threshold of session '{name}' must be a number of seconds")
+        })?;
+      file.sessions.insert(name, threshold);
+    }
+  }
+
+  let command = Args::command();
+  let mut args = vec![];
+  for (key, value) in table {
+    let arg = command
+      .get_arguments()
+      .find(|arg| {
+        arg.get_long() == Some(key.as_str())
+          || arg
+            .get_all_aliases()
+            .is_some_and(|a| a.contains(&key.as_str()))
+      })
+      .filter(|_| !CLI_ONLY.contains(&key.as_str()))
+      .with_context(|| format!("Unknown key '{key}'"))?;
+
+    // The command line takes precedence
+    let id = arg.get_id().as_str();
+    if matches.value_source(id) == Some(ValueSource::CommandLine) {
+      continue;
+    }
+
+    let values = match value {
+      Value::Array(values) => values,
+      value => vec![value],
+    };
+    for value in values {
+      let value = match value {
+        Value::Boolean(true) => {
+          args.push(format!("--{key}").into());
+          continue;
+        }
+        Value::Boolean(false) => continue,
+        Value::String(s) => s,
+        Value::Integer(n) => n.to_string(),
+        Value::Float(n) => n.to_string(),
+        _ => bail!("Unsupported value for key '{key}'"),
+      };
+      args.push(format!("--{key}={value}").into());
+    }
+  }
+
+  Ok((args, file))
+}
+
+/// Merge the profile given with `--profile`, or else the default profile,
+/// over the rest of `table`, and drop the profiles from it. Returns the
+/// name of the profile applied.
+fn select_profile(
+  table: &mut Table,
+  matches: &ArgMatches,
+) -> Result<Option<String>> {
+  let profiles = match table.remove(PROFILES_KEY) {
+    Some(Value::Table(profiles)) => profiles,
+    Some(_) => bail!("'{PROFILES_KEY}' must be a table"),
+    None => Table::new(),
+  };
+  let default = match table.remove(DEFAULT_PROFILE_KEY) {
+    Some(Value::String(name)) => Some(name),
+    Some(_) => bail!("'{DEFAULT_PROFILE_KEY}' must be a string"),
+    None => None,
+  };
+  let Some(name) = matches.get_one::<String>("profile").cloned().or(default)
+  else {
+    return Ok(None);
+  };
+
+  let Some(profile) = profiles.get(&name) else {
+    bail!("Unknown profile '{name}'");
+  };
+  let Value::Table(profile) = profile else {
+    bail!("Profile '{name}' must be a table");
+  };
+  for (key, value) in profile {
+    match (table.get_mut(key), value) {
+      // Session thresholds add up, with the profile's taking precedence
+      (Some(Value::Table(base)), Value::Table(overrides)) => {
+        base.extend(overrides.clone())
+      }
+      _ => {
+        table.insert(key.clone(), value.clone());
+      }
+    }
+  }
+  Ok(Some(name))
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigFormat {
+  Toml,
+  Json,
+}
+
+/// The configuration in effect after applying defaults, for
+/// `--print-config`.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct EffectiveConfig {
+  mode: Mode,
+  idle_enter: u64,
+  idle_exit: u64,
+  check_interval: f64,
+  align_polls: bool,
+  idle_source: String,
+  displays: Vec<String>,
+  x_connect_timeout: u64,
+  x_reply_timeout: u64,
+  #[cfg(feature = "libinput")]
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  ignore_device: Vec<String>,
+  watch_pointer: bool,
+  watch_network: Vec<String>,
+  strict_extension: bool,
+  verify_counter: bool,
+  strict_counter: bool,
+  dpms_off_on_idle: bool,
+  follow_screensaver: bool,
+  set_idle_only: bool,
+  quiet_hours: Vec<String>,
+  require_continuous_idle: bool,
+  smooth_window: usize,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  adaptive_threshold: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  max_reported_idle: Option<u64>,
+  #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+  sessions: BTreeMap<String, SessionThresholds>,
+  cross_check: bool,
+  allow_no_dbus: bool,
+  ignore_permission_errors: bool,
+  non_interactive: bool,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  dbus_connect_delay: Option<u64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  reassert_interval: Option<u64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  max_runtime: Option<u64>,
+  exit_hint: bool,
+  hooks: Hooks,
+  retry: Retry,
+  log_level: LogLevel,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  log_file: Option<PathBuf>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  audit_file: Option<PathBuf>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  history_size: Option<u32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  summary_json: Option<PathBuf>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  state_file: Option<PathBuf>,
+  state_format: StateFormat,
+  #[cfg(feature = "health")]
+  #[serde(skip_serializing_if = "Option::is_none")]
+  health_addr: Option<String>,
+  #[cfg(feature = "metrics")]
+  #[serde(skip_serializing_if = "Option::is_none")]
+  statsd_addr: Option<String>,
+  bus: &'static str,
+  session_path: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+struct SessionThresholds {
+  idle_enter: u64,
+  idle_exit: u64,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+struct Hooks {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  on_idle: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  on_active: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  on_suspend: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  on_resume: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  suspend_hook_timeout: Option<u64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  on_exit: Option<String>,
+  on_exit_timeout: u64,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+struct Retry {
+  initial_delay: u64,
+  max_delay: u64,
+  multiplier: f64,
+  max_attempts: u32,
+}
+
+impl EffectiveConfig {
+  pub fn new(
+    args: &Args,
+    config: &Config,
+    log_level: LogLevel,
+    session_path: String,
+  ) -> Self {
+    Self {
+      mode: config.mode,
+      idle_enter: config.thresholds.enter.as_secs(),
+      idle_exit: config.thresholds.exit.as_secs(),
+      check_interval: monitor::check_interval(config).as_secs_f64(),
+      align_polls: config.align_polls,
+      idle_source: config.source.spec.to_string(),
+      displays: config.source.displays.clone(),
+      x_connect_timeout: config.source.x11.connect_timeout.as_secs(),
+      x_reply_timeout: config.source.x11.reply_timeout.as_secs(),
+      #[cfg(feature = "libinput")]
+      ignore_device: config.source.ignore_devices.clone(),
+      watch_pointer: config.source.x11.watch_pointer,
+      watch_network: config
+        .watch_networks
+        .iter()
+        .map(ToString::to_string)
+        .collect(),
+      strict_extension: config.source.x11.strict_extension,
+      verify_counter: config.source.x11.verify_counter,
+      strict_counter: config.source.x11.strict_counter,
+      dpms_off_on_idle: config.source.x11.dpms,
+      follow_screensaver: config.follow_screensaver,
+      set_idle_only: config.set_idle_only,
+      quiet_hours: config.quiet_hours.iter().map(ToString::to_string).collect(),
+      require_continuous_idle: config.require_continuous_idle,
+      smooth_window: config.smooth_window,
+      adaptive_threshold: config
+        .adaptive_threshold
+        .as_ref()
+        .map(ToString::to_string),
+      max_reported_idle: args.max_reported_idle,
+      sessions: config
+        .session_thresholds
+        .iter()
+        .map(|(name, thresholds)| {
+          let thresholds = SessionThresholds {
+            idle_enter: thresholds.enter.as_secs(),
+            idle_exit: thresholds.exit.as_secs(),
+          };
+          (name.clone(), thresholds)
+        })
+        .collect(),
+      cross_check: config.cross_check,
+      allow_no_dbus: config.allow_no_dbus,
+      ignore_permission_errors: config.ignore_permission_errors,
+      non_interactive: args.non_interactive,
+      dbus_connect_delay: args.dbus_connect_delay,
+      reassert_interval: args.reassert_interval,
+      max_runtime: args.max_runtime,
+      exit_hint: !args.no_exit_hint,
+      hooks: Hooks {
+        on_idle: args.on_idle.clone(),
+        on_active: args.on_active.clone(),
+        on_suspend: args.on_suspend.clone(),
+        on_resume: args.on_resume.clone(),
+        suspend_hook_timeout: args.suspend_hook_timeout,
+        on_exit: args.on_exit.clone(),
+        on_exit_timeout: args.on_exit_timeout,
+      },
+      retry: Retry {
+        initial_delay: args.retry_initial_delay,
+        max_delay: args.retry_max_delay,
+        multiplier: args.retry_multiplier,
+        max_attempts: args.retry_max_attempts,
+      },
+      log_level,
+      log_file: args.log_file.clone(),
+      audit_file: args.audit_file.clone(),
+      history_size: args.history_size,
+      summary_json: args.summary_json.clone(),
+      state_file: args.state_file.clone(),
+      state_format: args.state_format,
+      #[cfg(feature = "health")]
+      health_addr: args.health_addr.as_ref().map(ToString::to_string),
+      #[cfg(feature = "metrics")]
+      statsd_addr: args.statsd_addr.clone(),
+      bus: "system",
+      session_path,
+    }
+  }
+
+  pub fn print(&self, format: ConfigFormat) -> Result<()> {
+    match format {
+      ConfigFormat::Toml => print!("{}", toml::to_string(self)?),
+      ConfigFormat::Json => println!("{}", serde_json::to_string_pretty(self)?),
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,69 @@
+use std::{fmt, process::ExitCode};
+
+/// Failures that are reported with their own exit status, so that process
+/// supervisors can tell whether restarting is worthwhile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fatal {
+  /// Invalid configuration, restarting won't help
+  Config,
+  /// The X display is gone and could not be reconnected
+  XUnavailable,
+  /// The system bus or logind could not be reached
+  DbusUnavailable,
+  /// Another instance is syncing the same session
+  AlreadyRunning,
+  /// logind doesn't let us set the idle hint of the session
+  PermissionDenied,
+}
+
+impl Fatal {
+  fn code(self) -> u8 {
+    match self {
+      Self::Config => 2,
+      Self::AlreadyRunning => 3,
+      Self::XUnavailable => 10,
+      Self::DbusUnavailable => 11,
+      Self::PermissionDenied => 12,
+    }
+  }
+}
+
+impl fmt::Display for Fatal {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      Self::Config => "Invalid configuration",
+      Self::XUnavailable => "X display unavailable",
+      Self::DbusUnavailable => "D-Bus unavailable",
+      Self::AlreadyRunning => "Already running",
+      Self::PermissionDenied => "Not permitted to set the idle hint",
+    })
+  }
+}
+
+impl std::error::Error for Fatal {}
+
+/// Exit status for a failed run. Any D-Bus error that wasn't tagged
+/// otherwise counts as D-Bus being unavailable.
+pub fn code(error: &anyhow::Error) -> ExitCode {
+  let fatal = error.downcast_ref::<Fatal>().copied().or_else(|| {
+    error
+      .chain()
+      .any(|cause| cause.is::<zbus::Error>())
+      .then_some(Fatal::DbusUnavailable)
+  });
+
+  match fatal {
+    Some(fatal) => ExitCode::from(fatal.code()),
+    None => ExitCode::FAILURE,
+  }
+}
+
+pub const HELP: &str = "\
+Exit status:
+  0   Clean shutdown
+  1   Other error
+  2   Invalid configuration
+  3   Another instance is already running
+  10  X display unavailable and unrecoverable
+  11  D-Bus unavailable
+  12  Not permitted to set the idle hint";
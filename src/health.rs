@@ -0,0 +1,335 @@
+use anyhow::{Context, Result};
+use std::{
+  env, fmt, io,
+  net::{IpAddr, SocketAddr},
+  ops::RangeInclusive,
+  os::fd::{FromRawFd, OwnedFd},
+  process,
+  str::FromStr,
+  time::{Duration, Instant, UNIX_EPOCH},
+};
+use tokio::{
+  io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+  net::{TcpListener, UnixListener},
+  sync::watch,
+  time::timeout,
+};
+use tracing::{debug, info, warn};
+
+use crate::monitor::Status;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_HEADER_LINES: usize = 100;
+/// The first file descriptor passed by systemd socket activation
+const LISTEN_FDS_START: i32 = 3;
+
+/// An IP address with a port, or a range of ports to take the first free
+/// one of, like `127.0.0.1:9184-9199` or `[::1]:9184`.
+#[derive(Clone, Debug)]
+pub struct HealthAddr {
+  ip: IpAddr,
+  ports: RangeInclusive<u16>,
+}
+
+impl FromStr for HealthAddr {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let invalid =
+      || format!("invalid address '{s}', expected <ip>:<port>[-<last port>]");
+    let (host, ports) = s.rsplit_once(':').ok_or_else(invalid)?;
+    let host = match host.strip_prefix('[') {
+      Some(host) => host.strip_suffix(']').ok_or_else(invalid)?,
+      // An IPv6 address without brackets is ambiguous with the port
+      None if host.contains(':') => return Err(invalid()),
+      None => host,
+    };
+    let ip = host.parse().map_err(|_| invalid())?;
+    let (first, last) = ports.split_once('-').unwrap_or((ports, ports));
+    let first = first.parse().map_err(|_| invalid())?;
+    let last = last.parse().map_err(|_| invalid())?;
+    if first > last {
+      return Err(format!("invalid port range '{ports}', {first} > {last}"));
+    }
+    Ok(Self {
+      ip,
+      ports: first..=last,
+    })
+  }
+}
+
+impl fmt::Display for HealthAddr {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let (first, last) = (self.ports.start(), self.ports.end());
+    write!(f, "{}", SocketAddr::new(self.ip, *first))?;
+    if first != last {
+      write!(f, "-{last}")?;
+    }
+    Ok(())
+  }
+}
+
+impl HealthAddr {
+  /// Bind to the first port in the range that is not in use.
+  async fn bind(&self) -> Result<TcpListener> {
+    let mut result = Err(io::ErrorKind::AddrInUse.into());
+    for port in self.ports.clone() {
+      result = TcpListener::bind((self.ip, port)).await;
+      match &result {
+        Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+          debug!("Port {port} is in use");
+        }
+        _ => break,
+      }
+    }
+    result.with_context(|| format!("Failed to bind health endpoint to {self}"))
+  }
+}
+
+pub enum Listener {
+  Tcp(TcpListener),
+  Unix(UnixListener),
+}
+
+trait Connection: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Connection for T {}
+
+/// The sockets passed by systemd socket activation.
+pub struct Activated {
+  /// The first one of them
+  fd: OwnedFd,
+  count: u32,
+}
+
+impl Activated {
+  /// Take the sockets passed in `$LISTEN_FDS` if they are meant for this
+  /// process, and clear the variables like sd_listen_fds() so that hooks
+  /// don't take them as their own. Changing the environment is only sound
+  /// while no other threads run, so this has to come first in `main`.
+  pub fn take() -> Option<Self> {
+    let pid = env::var("LISTEN_PID").ok();
+    let count = env::var("LISTEN_FDS").ok();
+    for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+      env::remove_var(var);
+    }
+
+    if pid.and_then(|pid| pid.parse().ok()) != Some(process::id()) {
+      return None;
+    }
+    let count = count.and_then(|count| count.parse().ok()).unwrap_or(0);
+    if count == 0 {
+      return None;
+    }
+    // SAFETY: systemd hands over the sockets from fd 3 on, and nothing
+    // else in this process uses that descriptor
+    let fd = unsafe { OwnedFd::from_raw_fd(LISTEN_FDS_START) };
+    Some(Self { fd, count })
+  }
+}
+
+impl Listener {
+  /// The socket passed by systemd socket activation if there is one, or
+  /// else a socket bound to `addr` if given.
+  pub async fn new(
+    addr: Option<&HealthAddr>,
+    activated: Option<Activated>,
+  ) -> Result<Option<Self>> {
+    if let Some(activated) = activated {
+      let listener = Self::adopt(activated)?;
+      if addr.is_some() {
+        warn!("Ignoring --health-addr in favor of the socket from systemd");
+      }
+      info!("Serving health endpoint on the socket passed by systemd");
+      return Ok(Some(listener));
+    }
+
+    let Some(addr) = addr else {
+      return Ok(None);
+    };
+    let listener = addr.bind().await?;
+    let addr = listener
+      .local_addr()
+      .context("Failed to read the health endpoint's address")?;
+    info!("Serving health endpoint on http://{addr}/healthz");
+    Ok(Some(Self::Tcp(listener)))
+  }
+
+  /// Adopt the first socket passed by systemd, TCP or Unix.
+  fn adopt(activated: Activated) -> Result<Self> {
+    let Activated { fd, count } = activated;
+    if count > 1 {
+      warn!("Got {count} sockets from systemd, using only the first");
+    }
+    // Passed sockets are inherited across exec, while their duplicates are
+    // close-on-exec and so stay out of hooks
+    let context = "Failed to adopt the socket passed by systemd";
+    let tcp = std::net::TcpListener::from(fd);
+    if tcp.local_addr().is_ok() {
+      let listener = tcp.try_clone().context(context)?;
+      listener.set_nonblocking(true).context(context)?;
+      return Ok(Self::Tcp(TcpListener::from_std(listener).context(context)?));
+    }
+
+    let unix = std::os::unix::net::UnixListener::from(OwnedFd::from(tcp));
+    unix
+      .local_addr()
+      .context("The socket passed by systemd is neither TCP nor Unix")?;
+    let listener = unix.try_clone().context(context)?;
+    listener.set_nonblocking(true).context(context)?;
+    Ok(Self::Unix(
+      UnixListener::from_std(listener).context(context)?,
+    ))
+  }
+
+  async fn accept(&self) -> io::Result<Box<dyn Connection>> {
+    Ok(match self {
+      Self::Tcp(listener) => Box::new(listener.accept().await?.0),
+      Self::Unix(listener) => Box::new(listener.accept().await?.0),
+    })
+  }
+}
+
+/// Serve `/healthz` on `listener`. The endpoint reports 503 when the last
+/// poll failed or no poll succeeded within `max_age`.
+pub fn spawn(
+  listener: Listener,
+  status: watch::Receiver<Status>,
+  max_age: Duration,
+) {
+  let started = Instant::now();
+  tokio::spawn(async move {
+    loop {
+      let stream = match listener.accept().await {
+        Ok(stream) => stream,
+        Err(e) => {
+          debug!("Failed to accept health connection: {e}");
+          continue;
+        }
+      };
+
+      let status = *status.borrow();
+      tokio::spawn(async move {
+        let healthy = is_healthy(&status, started, max_age);
+        if let Err(e) = respond(stream, &status, healthy).await {
+          debug!("Failed to serve health request: {e:#}");
+        }
+      });
+    }
+  });
+}
+
+fn is_healthy(status: &Status, started: Instant, max_age: Duration) -> bool {
+  let last_success = status.last_success.unwrap_or(started);
+  !status.last_poll_failed && last_success.elapsed() <= max_age
+}
+
+async fn respond(
+  stream: Box<dyn Connection>,
+  status: &Status,
+  healthy: bool,
+) -> Result<()> {
+  let mut stream = BufReader::new(stream);
+
+  let mut request_line = String::new();
+  timeout(REQUEST_TIMEOUT, async {
+    stream.read_line(&mut request_line).await?;
+    // Drain the headers, we don't care about any of them
+    let mut line = String::new();
+    for _ in 0..MAX_HEADER_LINES {
+      line.clear();
+      if stream.read_line(&mut line).await? == 0 || line.trim().is_empty() {
+        break;
+      }
+    }
+    anyhow::Ok(())
+  })
+  .await
+  .context("Timed out reading request")??;
+
+  let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+  let (code, body) = if path != "/healthz" {
+    ("404 Not Found", String::new())
+  } else {
+    let code = if healthy {
+      "200 OK"
+    } else {
+      "503 Service Unavailable"
+    };
+    let idle_since = match status.idle_since {
+      Some(since) => {
+        let since = since.duration_since(UNIX_EPOCH).unwrap_or_default();
+        format!(r#","idle_since":{}"#, since.as_secs())
+      }
+      None => String::new(),
+    };
+    let body = format!(
+      r#"{{"state":"{}","idle_ms":{}{idle_since}}}"#,
+      if status.idle { "idle" } else { "active" },
+      status.idle_time.as_millis()
+    );
+    (code, body)
+  };
+
+  let response = format!(
+    "HTTP/1.1 {code}\r\n\
+     Content-Type: application/json\r\n\
+     Content-Length: {}\r\n\
+     Connection: close\r\n\
+     \r\n\
+     {body}",
+    body.len()
+  );
+  stream.get_mut().write_all(response.as_bytes()).await?;
+  stream.get_mut().shutdown().await?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_port_ranges() {
+    let addr: HealthAddr = "127.0.0.1:9184-9199".parse().unwrap();
+    assert_eq!(addr.ports, 9184..=9199);
+    assert_eq!(addr.to_string(), "127.0.0.1:9184-9199");
+
+    let addr: HealthAddr = "[::1]:9184".parse().unwrap();
+    assert_eq!(addr.ports, 9184..=9184);
+    assert_eq!(addr.to_string(), "[::1]:9184");
+  }
+
+  #[test]
+  fn rejects_reversed_ranges() {
+    let err = "127.0.0.1:9199-9184".parse::<HealthAddr>().unwrap_err();
+    assert_eq!(err, "invalid port range '9199-9184', 9199 > 9184");
+  }
+
+  #[test]
+  fn rejects_malformed_addresses() {
+    for addr in [
+      "9184",
+      "localhost:9184",
+      "::1:9184",
+      "127.0.0.1:",
+      "127.0.0.1:9184-",
+      "127.0.0.1:65536",
+    ] {
+      assert!(addr.parse::<HealthAddr>().is_err(), "{addr}");
+    }
+  }
+
+  #[tokio::test]
+  async fn binds_next_free_port() {
+    let taken = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = taken.local_addr().unwrap().port();
+    let Some(last) = port.checked_add(1) else {
+      return;
+    };
+    let addr: HealthAddr = format!("127.0.0.1:{port}-{last}").parse().unwrap();
+    // The next port may be taken by someone else as well
+    if let Ok(listener) = addr.bind().await {
+      assert_eq!(listener.local_addr().unwrap().port(), last);
+    }
+  }
+}
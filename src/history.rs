@@ -0,0 +1,102 @@
+use anyhow::{Context, Result};
+use std::{
+  collections::VecDeque,
+  sync::{Arc, Mutex, MutexGuard, PoisonError},
+  time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use zbus::{connection, interface, Connection};
+
+use crate::monitor::Reason;
+
+const BUS_NAME: &str = "io.github.shouya.X11IdleSync";
+const OBJECT_PATH: &str = "/io/github/shouya/X11IdleSync";
+
+#[derive(Debug)]
+struct Transition {
+  time: SystemTime,
+  idle: bool,
+  idle_time: Duration,
+  reason: Reason,
+}
+
+/// The most recent idle state transitions, oldest first. Once full, every
+/// new transition pushes out the oldest one.
+#[derive(Clone, Debug)]
+pub struct History {
+  size: usize,
+  transitions: Arc<Mutex<VecDeque<Transition>>>,
+}
+
+impl History {
+  pub fn new(size: usize) -> Self {
+    Self {
+      size,
+      transitions: Arc::new(Mutex::new(VecDeque::with_capacity(size))),
+    }
+  }
+
+  /// Remember a transition of the user becoming idle or active.
+  pub fn record(&self, idle: bool, idle_time: Duration, reason: Reason) {
+    let mut transitions = self.lock();
+    if transitions.len() == self.size {
+      transitions.pop_front();
+    }
+    transitions.push_back(Transition {
+      time: SystemTime::now(),
+      idle,
+      idle_time,
+      reason,
+    });
+  }
+
+  fn lock(&self) -> MutexGuard<'_, VecDeque<Transition>> {
+    self
+      .transitions
+      .lock()
+      .unwrap_or_else(PoisonError::into_inner)
+  }
+}
+
+/// The D-Bus face of the history.
+struct Service {
+  history: History,
+}
+
+#[interface(name = "io.github.shouya.X11IdleSync")]
+impl Service {
+  /// Recent transitions, oldest first, as (microseconds since the epoch,
+  /// `idle` or `active`, idle time in milliseconds, reason).
+  fn get_recent_transitions(&self) -> Vec<(u64, String, u64, String)> {
+    self
+      .history
+      .lock()
+      .iter()
+      .map(|transition| {
+        let time = transition
+          .time
+          .duration_since(UNIX_EPOCH)
+          .unwrap_or_default()
+          .as_micros() as u64;
+        let state = if transition.idle { "idle" } else { "active" };
+        (
+          time,
+          state.to_string(),
+          transition.idle_time.as_millis() as u64,
+          transition.reason.to_string(),
+        )
+      })
+      .collect()
+  }
+}
+
+/// Serve `history` on the session bus. The connection has to be kept for as
+/// long as it should be served.
+pub async fn serve(history: History) -> Result<Connection> {
+  connection::Builder::session()
+    .context("Failed to connect to session D-Bus")?
+    .name(BUS_NAME)?
+    .serve_at(OBJECT_PATH, Service { history })?
+    .build()
+    .await
+    .with_context(|| format!("Failed to serve {BUS_NAME}"))
+}
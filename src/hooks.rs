@@ -0,0 +1,70 @@
+use std::time::{Duration, UNIX_EPOCH};
+use tokio::{process::Command, time::timeout};
+use tracing::{debug, info, warn};
+
+use crate::monitor::Status;
+
+/// The situation a hook runs in, passed on in its environment.
+#[derive(Clone, Debug)]
+pub struct Env {
+  pub status: Status,
+  /// The idle source, as given to `--idle-source`
+  pub backend: String,
+}
+
+impl Env {
+  fn apply(&self, command: &mut Command) {
+    let status = &self.status;
+    command
+      .env("STATE", if status.idle { "idle" } else { "active" })
+      .env("IDLE_MS", status.idle_time.as_millis().to_string())
+      .env("THRESHOLD_MS", status.threshold.as_millis().to_string())
+      .env("BACKEND", &self.backend);
+    match status.reason {
+      Some(reason) => command.env("REASON", reason.to_string()),
+      None => command.env_remove("REASON"),
+    };
+    match status.idle_since {
+      Some(since) => {
+        let secs = since.duration_since(UNIX_EPOCH).unwrap_or_default();
+        command.env("IDLE_SINCE", secs.as_secs().to_string())
+      }
+      None => command.env_remove("IDLE_SINCE"),
+    };
+  }
+}
+
+/// Run `cmd` through `sh -c` with `env` in its environment and log
+/// its outcome. If `limit` is given, wait at most that long for it to
+/// finish and leave it running in the background afterwards.
+pub async fn run(name: &str, cmd: &str, limit: Option<Duration>, env: &Env) {
+  info!("Running {name} hook");
+  let mut command = Command::new("sh");
+  command.arg("-c").arg(cmd);
+  env.apply(&mut command);
+  let mut child = match command.spawn() {
+    Ok(child) => child,
+    Err(e) => {
+      warn!("Failed to run {name} hook: {e}");
+      return;
+    }
+  };
+
+  let status = match limit {
+    Some(limit) => match timeout(limit, child.wait()).await {
+      Ok(status) => status,
+      Err(_) => {
+        warn!("The {name} hook is still running after {limit:?}, moving on");
+        tokio::spawn(async move { child.wait().await });
+        return;
+      }
+    },
+    None => child.wait().await,
+  };
+
+  match status {
+    Ok(status) if status.success() => debug!("The {name} hook finished"),
+    Ok(status) => warn!("The {name} hook failed: {status}"),
+    Err(e) => warn!("Failed to wait for {name} hook: {e}"),
+  }
+}
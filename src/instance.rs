@@ -0,0 +1,95 @@
+use anyhow::{anyhow, bail, Context, Result};
+use std::{
+  io::ErrorKind,
+  os::{
+    linux::net::SocketAddrExt,
+    unix::{
+      fs::MetadataExt,
+      net::{SocketAddr, UnixListener, UnixStream},
+    },
+  },
+  sync::Arc,
+  time::{Duration, Instant},
+};
+use tokio::{sync::Notify, time::sleep};
+use tracing::{info, warn};
+
+use crate::exit::Fatal;
+
+/// How long to wait for a running instance to quit on `--takeover`.
+const TAKEOVER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Make sure this is the only instance syncing the session of the current
+/// user on `x_display`.
+///
+/// The guard is an abstract Unix socket that lives as long as the process.
+/// Connecting to it asks the owner to quit, which is notified on `quit`;
+/// with `takeover`, this is how a running instance is replaced.
+pub async fn acquire(
+  x_display: Option<&str>,
+  takeover: bool,
+  quit: Arc<Notify>,
+) -> Result<()> {
+  let uid = std::fs::metadata("/proc/self")
+    .context("Failed to determine own uid")?
+    .uid();
+  let name = format!("x11-idle-sync/{uid}/{}", x_display.unwrap_or("none"));
+  let addr = SocketAddr::from_abstract_name(&name)
+    .context("Failed to create instance socket address")?;
+
+  let listener = match UnixListener::bind_addr(&addr) {
+    Ok(listener) => listener,
+    Err(e) if e.kind() == ErrorKind::AddrInUse && takeover => {
+      info!("Asking the running instance to quit");
+      UnixStream::connect_addr(&addr)
+        .context("Failed to contact the running instance")?;
+      wait_for_release(&addr).await?
+    }
+    Err(e) if e.kind() == ErrorKind::AddrInUse => {
+      let display = match x_display {
+        Some(x_display) => format!(" for display {x_display}"),
+        None => String::new(),
+      };
+      return Err(anyhow!(
+        "Another instance is already running{display}, use --takeover to \
+         replace it"
+      ))
+      .context(Fatal::AlreadyRunning);
+    }
+    Err(e) => return Err(e).context("Failed to bind instance socket"),
+  };
+
+  listener.set_nonblocking(true)?;
+  let listener = tokio::net::UnixListener::from_std(listener)?;
+  tokio::spawn(async move {
+    loop {
+      match listener.accept().await {
+        Ok(_) => {
+          info!("Another instance is taking over, exiting");
+          quit.notify_one();
+        }
+        Err(e) => {
+          warn!("Failed to accept on instance socket: {e}");
+          return;
+        }
+      }
+    }
+  });
+  Ok(())
+}
+
+async fn wait_for_release(addr: &SocketAddr) -> Result<UnixListener> {
+  let start = Instant::now();
+  loop {
+    match UnixListener::bind_addr(addr) {
+      Ok(listener) => return Ok(listener),
+      Err(e) if e.kind() == ErrorKind::AddrInUse => {
+        if start.elapsed() > TAKEOVER_TIMEOUT {
+          bail!("The running instance didn't quit");
+        }
+        sleep(Duration::from_millis(100)).await;
+      }
+      Err(e) => return Err(e).context("Failed to bind instance socket"),
+    }
+  }
+}
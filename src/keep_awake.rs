@@ -0,0 +1,61 @@
+use anyhow::{Context, Result};
+use std::time::Duration;
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::{info, warn};
+use zbus::Connection;
+
+use crate::{
+  exit::Fatal,
+  logind::{self, HintMethod, Login1ManagerProxy, Login1SessionProxy},
+  ExitSignals,
+};
+
+/// Keep the session of `x_display` active until a signal arrives: hold an
+/// idle inhibitor lock and reset the idle hint every `period`. The lock is
+/// released on return.
+pub async fn run(
+  x_display: Option<String>,
+  period: Duration,
+  non_interactive: bool,
+  mut signals: ExitSignals,
+) -> Result<()> {
+  let conn = Connection::system()
+    .await
+    .context("Failed to connect to system D-Bus")
+    .context(Fatal::DbusUnavailable)?;
+  let manager = Login1ManagerProxy::new(&conn).await?;
+  let lock = logind::inhibit(
+    &manager,
+    "idle",
+    "Keeping the session awake",
+    "block",
+    non_interactive,
+  )
+  .await?;
+
+  let session_path = logind::resolve_session(&conn, x_display.as_deref()).await;
+  let session = Login1SessionProxy::builder(&conn)
+    .path(session_path)?
+    .build()
+    .await
+    .context("Failed to create Login1Session proxy")?;
+  info!("Keeping the session awake until interrupted");
+
+  let mut ticker = interval(period);
+  ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+  let mut method = HintMethod::SetIdleHint;
+  loop {
+    tokio::select! {
+      _ = signals.recv() => break,
+      _ = ticker.tick() => {}
+    }
+    match logind::set_idle_hint(&session, false, method).await {
+      Ok(used) => method = used,
+      Err(e) => warn!("Failed to reset idle hint: {e}"),
+    }
+  }
+
+  drop(lock);
+  info!("Released idle inhibitor lock. Exiting.");
+  Ok(())
+}
@@ -0,0 +1,310 @@
+use anyhow::{bail, Context as _, Result};
+use std::{
+  collections::HashSet,
+  env,
+  ffi::{c_char, c_int, c_void, CStr, CString},
+  io,
+  os::fd::{AsRawFd, RawFd},
+  ptr,
+  sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex, MutexGuard, PoisonError,
+  },
+  time::{Duration, Instant},
+};
+use tokio::{io::unix::AsyncFd, task::JoinHandle};
+use tracing::{debug, warn};
+
+/// The seat to watch when the session doesn't name one.
+const DEFAULT_SEAT: &CStr = c"seat0";
+
+/// Takes the idle time from the input devices of the session's seat as
+/// libinput sees them, so that devices with spurious events can be left
+/// out. The devices are read by a task that stops when the source is
+/// dropped.
+pub struct LibinputSource {
+  shared: Arc<Shared>,
+  reader: JoinHandle<()>,
+}
+
+struct Shared {
+  last_event: Mutex<Instant>,
+  /// Devices being watched
+  devices: AtomicUsize,
+}
+
+impl LibinputSource {
+  /// Watch every input device of the seat except those named in `ignore`.
+  pub fn new(ignore: &[String]) -> Result<Self> {
+    let shared = Arc::new(Shared {
+      last_event: Mutex::new(Instant::now()),
+      devices: AtomicUsize::new(0),
+    });
+    let mut context = Context::new(ignore.to_vec())?;
+    // Picks up the devices libinput added for the seat
+    context.dispatch(&shared)?;
+    if shared.devices.load(Ordering::Relaxed) == 0 {
+      bail!(
+        "No input devices accessible to libinput, is the user in the input \
+         group?"
+      );
+    }
+
+    let context = AsyncFd::new(context).context("Failed to watch libinput")?;
+    let reader = tokio::spawn(read_events(context, shared.clone()));
+    Ok(Self { shared, reader })
+  }
+
+  /// Number of devices being watched.
+  pub fn device_count(&self) -> usize {
+    self.shared.devices.load(Ordering::Relaxed)
+  }
+
+  /// The time since the last event of a watched device.
+  pub fn idle_time(&self) -> Duration {
+    lock(&self.shared.last_event).elapsed()
+  }
+}
+
+impl Drop for LibinputSource {
+  fn drop(&mut self) {
+    self.reader.abort();
+  }
+}
+
+async fn read_events(mut context: AsyncFd<Context>, shared: Arc<Shared>) {
+  loop {
+    let result = match context.readable_mut().await {
+      Ok(mut ready) => {
+        let result = ready.get_inner_mut().dispatch(&shared);
+        ready.clear_ready();
+        result
+      }
+      Err(e) => Err(e).context("Failed to wait for input events"),
+    };
+    if let Err(e) = result {
+      warn!("Stopped reading input devices: {e:#}");
+      return;
+    }
+  }
+}
+
+/// A libinput context for the devices of the seat.
+struct Context {
+  libinput: *mut c_void,
+  ignore: Vec<String>,
+  /// Devices left out, by address while they are added
+  ignored: HashSet<usize>,
+}
+
+// SAFETY: libinput isn't thread-safe, but a context is only ever used by
+// whoever owns it
+unsafe impl Send for Context {}
+
+impl Context {
+  fn new(ignore: Vec<String>) -> Result<Self> {
+    // SAFETY: the context takes a reference of its own to udev, and
+    // INTERFACE outlives it
+    let libinput = unsafe {
+      let udev = ffi::udev_new();
+      if udev.is_null() {
+        bail!("Failed to initialize udev");
+      }
+      let libinput =
+        ffi::libinput_udev_create_context(&INTERFACE, ptr::null_mut(), udev);
+      ffi::udev_unref(udev);
+      libinput
+    };
+    if libinput.is_null() {
+      bail!("Failed to create a libinput context");
+    }
+
+    let context = Self {
+      libinput,
+      ignore,
+      ignored: HashSet::new(),
+    };
+    let seat = session_seat();
+    // SAFETY: the context is valid and the seat is a C string
+    let ret =
+      unsafe { ffi::libinput_udev_assign_seat(libinput, seat.as_ptr()) };
+    if ret != 0 {
+      bail!("Failed to assign libinput to {}", seat.to_string_lossy());
+    }
+    Ok(context)
+  }
+
+  /// Read the devices and process their events.
+  fn dispatch(&mut self, shared: &Shared) -> Result<()> {
+    // SAFETY: the context is valid
+    let ret = unsafe { ffi::libinput_dispatch(self.libinput) };
+    if ret < 0 {
+      return Err(io::Error::from_raw_os_error(-ret))
+        .context("Failed to read input devices");
+    }
+
+    while let Some(event) = self.next_event() {
+      let device = event.device();
+      let id = device as usize;
+      match event.kind() {
+        ffi::EVENT_DEVICE_ADDED => {
+          let name = device_name(device);
+          if self.ignore.contains(&name) {
+            debug!("Ignoring input device {name}");
+            self.ignored.insert(id);
+          } else {
+            debug!("Watching input device {name}");
+            shared.devices.fetch_add(1, Ordering::Relaxed);
+          }
+        }
+        ffi::EVENT_DEVICE_REMOVED => {
+          // Ignored devices were never counted
+          let watched = !self.ignored.remove(&id);
+          if watched {
+            shared.devices.fetch_sub(1, Ordering::Relaxed);
+          }
+        }
+        // Any other event counts, there is no need to decode them
+        _ if !self.ignored.contains(&id) => {
+          *lock(&shared.last_event) = Instant::now();
+        }
+        _ => {}
+      }
+    }
+    Ok(())
+  }
+
+  fn next_event(&mut self) -> Option<Event> {
+    // SAFETY: the context is valid
+    let raw = unsafe { ffi::libinput_get_event(self.libinput) };
+    (!raw.is_null()).then_some(Event { raw })
+  }
+}
+
+impl AsRawFd for Context {
+  fn as_raw_fd(&self) -> RawFd {
+    // SAFETY: the context is valid
+    unsafe { ffi::libinput_get_fd(self.libinput) }
+  }
+}
+
+impl Drop for Context {
+  fn drop(&mut self) {
+    // SAFETY: this is the only reference, which also closes the devices
+    unsafe { ffi::libinput_unref(self.libinput) };
+  }
+}
+
+/// An event taken from the context, freed on drop.
+struct Event {
+  raw: *mut c_void,
+}
+
+impl Event {
+  fn kind(&self) -> c_int {
+    // SAFETY: the event is valid until dropped
+    unsafe { ffi::libinput_event_get_type(self.raw) }
+  }
+
+  /// The device of the event, valid at least as long as the event.
+  fn device(&self) -> *mut c_void {
+    // SAFETY: the event is valid until dropped
+    unsafe { ffi::libinput_event_get_device(self.raw) }
+  }
+}
+
+impl Drop for Event {
+  fn drop(&mut self) {
+    // SAFETY: the event is not used after this
+    unsafe { ffi::libinput_event_destroy(self.raw) };
+  }
+}
+
+/// The seat of the session as set by logind, or the default seat.
+fn session_seat() -> CString {
+  env::var("XDG_SEAT")
+    .ok()
+    .filter(|seat| !seat.is_empty())
+    .and_then(|seat| CString::new(seat).ok())
+    .unwrap_or_else(|| DEFAULT_SEAT.to_owned())
+}
+
+/// The kernel's name of an input device.
+fn device_name(device: *mut c_void) -> String {
+  // SAFETY: the device is valid and libinput always names it
+  unsafe { CStr::from_ptr(ffi::libinput_device_get_name(device)) }
+    .to_string_lossy()
+    .into_owned()
+}
+
+/// How libinput opens and closes the devices.
+static INTERFACE: ffi::Interface = ffi::Interface {
+  open_restricted,
+  close_restricted,
+};
+
+unsafe extern "C" fn open_restricted(
+  path: *const c_char,
+  flags: c_int,
+  _user_data: *mut c_void,
+) -> c_int {
+  let fd = libc::open(path, flags | libc::O_CLOEXEC);
+  if fd < 0 {
+    // libinput expects a negative errno
+    -io::Error::last_os_error()
+      .raw_os_error()
+      .unwrap_or(libc::EIO)
+  } else {
+    fd
+  }
+}
+
+unsafe extern "C" fn close_restricted(fd: c_int, _user_data: *mut c_void) {
+  libc::close(fd);
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+  mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// The part of libinput's and libudev's C API that is used here.
+mod ffi {
+  use std::ffi::{c_char, c_int, c_void};
+
+  #[repr(C)]
+  pub struct Interface {
+    pub open_restricted:
+      unsafe extern "C" fn(*const c_char, c_int, *mut c_void) -> c_int,
+    pub close_restricted: unsafe extern "C" fn(c_int, *mut c_void),
+  }
+
+  pub const EVENT_DEVICE_ADDED: c_int = 1;
+  pub const EVENT_DEVICE_REMOVED: c_int = 2;
+
+  #[link(name = "input")]
+  extern "C" {
+    pub fn libinput_udev_create_context(
+      interface: *const Interface,
+      user_data: *mut c_void,
+      udev: *mut c_void,
+    ) -> *mut c_void;
+    pub fn libinput_udev_assign_seat(
+      libinput: *mut c_void,
+      seat_id: *const c_char,
+    ) -> c_int;
+    pub fn libinput_get_fd(libinput: *mut c_void) -> c_int;
+    pub fn libinput_dispatch(libinput: *mut c_void) -> c_int;
+    pub fn libinput_get_event(libinput: *mut c_void) -> *mut c_void;
+    pub fn libinput_unref(libinput: *mut c_void) -> *mut c_void;
+    pub fn libinput_event_get_type(event: *mut c_void) -> c_int;
+    pub fn libinput_event_get_device(event: *mut c_void) -> *mut c_void;
+    pub fn libinput_event_destroy(event: *mut c_void);
+    pub fn libinput_device_get_name(device: *mut c_void) -> *const c_char;
+  }
+
+  #[link(name = "udev")]
+  extern "C" {
+    pub fn udev_new() -> *mut c_void;
+    pub fn udev_unref(udev: *mut c_void) -> *mut c_void;
+  }
+}
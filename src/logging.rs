@@ -0,0 +1,210 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::Serialize;
+#[cfg(feature = "tui")]
+use std::collections::VecDeque;
+use std::{
+  fs::{File, OpenOptions},
+  io::{self, Write},
+  path::{Path, PathBuf},
+  sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{info, level_filters::LevelFilter, warn};
+use tracing_subscriber::fmt::MakeWriter;
+
+#[derive(ValueEnum, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogLevel {
+  Error,
+  Warn,
+  Info,
+  Debug,
+  Trace,
+}
+
+impl From<LogLevel> for LevelFilter {
+  fn from(level: LogLevel) -> Self {
+    match level {
+      LogLevel::Error => LevelFilter::ERROR,
+      LogLevel::Warn => LevelFilter::WARN,
+      LogLevel::Info => LevelFilter::INFO,
+      LogLevel::Debug => LevelFilter::DEBUG,
+      LogLevel::Trace => LevelFilter::TRACE,
+    }
+  }
+}
+
+/// Number of log lines kept while the status view covers the terminal.
+#[cfg(feature = "tui")]
+const HELD_LINES: usize = 100;
+
+enum Sink {
+  Stderr,
+  File(File),
+  /// Lines held back from stderr while the status view is shown
+  #[cfg(feature = "tui")]
+  Held(VecDeque<String>),
+}
+
+/// Destination of the log output. It is shared with the tracing subscriber
+/// so that the log file can be swapped out while the daemon is running.
+#[derive(Clone)]
+pub struct LogOutput {
+  path: Option<PathBuf>,
+  sink: Arc<Mutex<Sink>>,
+}
+
+impl LogOutput {
+  fn new(path: Option<&Path>) -> Result<Self> {
+    let sink = match path {
+      Some(path) => Sink::File(open_log_file(path)?),
+      None => Sink::Stderr,
+    };
+
+    Ok(Self {
+      path: path.map(Path::to_path_buf),
+      sink: Arc::new(Mutex::new(sink)),
+    })
+  }
+
+  fn lock(&self) -> MutexGuard<'_, Sink> {
+    self.sink.lock().unwrap_or_else(PoisonError::into_inner)
+  }
+
+  /// Reopen the log file so that a rotated file gets replaced by a fresh
+  /// one. Falls back to stderr if the file can't be opened.
+  pub fn reopen(&self) {
+    let Some(path) = &self.path else {
+      return;
+    };
+
+    // Every event is written out in full while holding the lock, so
+    // swapping the sink under the lock never drops or splits a line.
+    let mut sink = self.lock();
+    match open_log_file(path) {
+      Ok(file) => *sink = Sink::File(file),
+      Err(e) => {
+        *sink = Sink::Stderr;
+        drop(sink);
+        warn!("{e:#}, logging to stderr instead");
+      }
+    }
+  }
+
+  /// Keep terminal output off stderr, where it would garble the status
+  /// view, until [`Self::release`]. Logging to a file is unaffected.
+  #[cfg(feature = "tui")]
+  pub fn hold(&self) {
+    let mut sink = self.lock();
+    if let Sink::Stderr = *sink {
+      *sink = Sink::Held(VecDeque::new());
+    }
+  }
+
+  /// The latest `count` lines held back, oldest first.
+  #[cfg(feature = "tui")]
+  pub fn held_lines(&self, count: usize) -> Vec<String> {
+    match &*self.lock() {
+      Sink::Held(lines) => lines
+        .iter()
+        .skip(lines.len().saturating_sub(count))
+        .cloned()
+        .collect(),
+      _ => vec![],
+    }
+  }
+
+  /// Write the lines held back to stderr, and log there again.
+  #[cfg(feature = "tui")]
+  pub fn release(&self) {
+    let mut sink = self.lock();
+    if let Sink::Held(lines) = &*sink {
+      for line in lines {
+        eprintln!("{line}");
+      }
+      *sink = Sink::Stderr;
+    }
+  }
+
+  /// Reopen the log file whenever SIGHUP is received. Does nothing when
+  /// logging to stderr.
+  pub fn reopen_on_sighup(&self) -> Result<()> {
+    if self.path.is_none() {
+      return Ok(());
+    }
+
+    let mut sighup = signal(SignalKind::hangup())
+      .context("Failed to install SIGHUP handler")?;
+    let output = self.clone();
+
+    tokio::spawn(async move {
+      while sighup.recv().await.is_some() {
+        output.reopen();
+        info!("Received SIGHUP, reopened log file");
+      }
+    });
+
+    Ok(())
+  }
+}
+
+fn open_log_file(path: &Path) -> Result<File> {
+  OpenOptions::new()
+    .create(true)
+    .append(true)
+    .open(path)
+    .with_context(|| format!("Failed to open log file {}", path.display()))
+}
+
+pub struct LogWriter<'a>(MutexGuard<'a, Sink>);
+
+impl Write for LogWriter<'_> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    match &mut *self.0 {
+      Sink::Stderr => io::stderr().write(buf),
+      Sink::File(file) => file.write(buf),
+      #[cfg(feature = "tui")]
+      Sink::Held(lines) => {
+        for line in String::from_utf8_lossy(buf).lines() {
+          if lines.len() == HELD_LINES {
+            lines.pop_front();
+          }
+          lines.push_back(line.to_string());
+        }
+        Ok(buf.len())
+      }
+    }
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    match &mut *self.0 {
+      Sink::Stderr => io::stderr().flush(),
+      Sink::File(file) => file.flush(),
+      #[cfg(feature = "tui")]
+      Sink::Held(_) => Ok(()),
+    }
+  }
+}
+
+impl<'a> MakeWriter<'a> for LogOutput {
+  type Writer = LogWriter<'a>;
+
+  fn make_writer(&'a self) -> Self::Writer {
+    LogWriter(self.lock())
+  }
+}
+
+/// Install the global tracing subscriber, writing to `log_file` if given
+/// and to stderr otherwise. Stdout is left to the command output.
+pub fn init(log_file: Option<&Path>, level: LogLevel) -> Result<LogOutput> {
+  let output = LogOutput::new(log_file)?;
+
+  tracing_subscriber::fmt()
+    .with_max_level(LevelFilter::from(level))
+    .with_target(false)
+    .with_writer(output.clone())
+    .init();
+
+  Ok(output)
+}
@@ -0,0 +1,250 @@
+use anyhow::{Context, Result};
+use std::{fmt, os::unix::fs::MetadataExt, time::Duration};
+use tokio::time::timeout;
+use tracing::{info, warn};
+use zbus::{
+  proxy,
+  zvariant::{OwnedFd, OwnedObjectPath},
+  Connection,
+};
+
+pub const SELF_SESSION_PATH: &str = "/org/freedesktop/login1/session/self";
+
+/// How long calls checked by polkit may take with `--non-interactive`.
+const AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[proxy(
+  interface = "org.freedesktop.login1.Session",
+  default_service = "org.freedesktop.login1"
+)]
+pub trait Login1Session {
+  fn set_idle_hint(&self, idle: bool) -> zbus::Result<()>;
+
+  #[zbus(property)]
+  fn display(&self) -> zbus::Result<String>;
+
+  #[zbus(property)]
+  fn idle_hint(&self) -> zbus::Result<bool>;
+
+  /// When the idle hint last changed, in microseconds since the epoch
+  #[zbus(property)]
+  fn idle_since_hint(&self) -> zbus::Result<u64>;
+
+  #[zbus(property)]
+  fn id(&self) -> zbus::Result<String>;
+
+  #[zbus(property)]
+  fn seat(&self) -> zbus::Result<(String, OwnedObjectPath)>;
+}
+
+#[proxy(
+  interface = "org.freedesktop.login1.Manager",
+  default_service = "org.freedesktop.login1",
+  default_path = "/org/freedesktop/login1"
+)]
+pub trait Login1Manager {
+  #[allow(clippy::type_complexity)]
+  fn list_sessions(
+    &self,
+  ) -> zbus::Result<Vec<(String, u32, String, String, OwnedObjectPath)>>;
+
+  fn inhibit(
+    &self,
+    what: &str,
+    who: &str,
+    why: &str,
+    mode: &str,
+  ) -> zbus::Result<OwnedFd>;
+
+  #[zbus(signal)]
+  fn prepare_for_sleep(&self, start: bool) -> zbus::Result<()>;
+}
+
+/// Pick the session to sync the idle hint for: the session of the current
+/// user that owns X display `x_display`, or our own session if there isn't
+/// exactly one such session.
+pub async fn resolve_session(
+  conn: &Connection,
+  x_display: Option<&str>,
+) -> String {
+  let Some(x_display) = x_display else {
+    return SELF_SESSION_PATH.to_string();
+  };
+
+  match find_display_sessions(conn, x_display).await {
+    Ok(sessions) if sessions.len() == 1 => {
+      let (id, path) = &sessions[0];
+      info!("Using session {id} which owns display {x_display}");
+      path.to_string()
+    }
+    Ok(sessions) => {
+      info!(
+        "Found {} sessions owning display {x_display}, using own session",
+        sessions.len()
+      );
+      SELF_SESSION_PATH.to_string()
+    }
+    Err(e) => {
+      warn!("Failed to resolve session of display {x_display}: {e:#}");
+      info!("Using own session");
+      SELF_SESSION_PATH.to_string()
+    }
+  }
+}
+
+/// Take an inhibitor lock of type `what` for `why`. logind has polkit
+/// authorize this, but the call never allows interactive authorization, so
+/// polkit decides without prompting. With `non_interactive`, a decision
+/// that takes longer than [`AUTH_TIMEOUT`] fails the call as well.
+pub async fn inhibit(
+  manager: &Login1ManagerProxy<'_>,
+  what: &str,
+  why: &str,
+  mode: &str,
+  non_interactive: bool,
+) -> Result<OwnedFd> {
+  let call = manager.inhibit(what, "x11-idle-sync", why, mode);
+  let result = if non_interactive {
+    timeout(AUTH_TIMEOUT, call).await.with_context(|| {
+      format!("Timed out waiting for authorization of {what} inhibitor lock")
+    })?
+  } else {
+    call.await
+  };
+  result.with_context(|| format!("Failed to take {what} inhibitor lock"))
+}
+
+/// Whether logind refused a call because the caller doesn't own the
+/// session, e.g. `SetIdleHint` from outside of it.
+pub fn is_access_denied(error: &zbus::Error) -> bool {
+  match error {
+    zbus::Error::MethodError(name, _, _) => {
+      name.as_str() == "org.freedesktop.DBus.Error.AccessDenied"
+    }
+    zbus::Error::FDO(error) => {
+      matches!(**error, zbus::fdo::Error::AccessDenied(_))
+    }
+    _ => false,
+  }
+}
+
+fn is_unknown_method(error: &zbus::Error) -> bool {
+  match error {
+    zbus::Error::MethodError(name, _, _) => {
+      name.as_str() == "org.freedesktop.DBus.Error.UnknownMethod"
+    }
+    zbus::Error::FDO(error) => {
+      matches!(**error, zbus::fdo::Error::UnknownMethod(_))
+    }
+    _ => false,
+  }
+}
+
+/// How the idle hint is handed to logind.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HintMethod {
+  SetIdleHint,
+  /// Writing the `IdleHint` property, for logind versions without
+  /// `SetIdleHint`
+  Properties,
+}
+
+impl fmt::Display for HintMethod {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      Self::SetIdleHint => "SetIdleHint",
+      Self::Properties => "org.freedesktop.DBus.Properties.Set",
+    })
+  }
+}
+
+/// Set the idle hint of `session`, starting with `method`. When logind
+/// doesn't know `SetIdleHint`, the `IdleHint` property is set instead.
+/// Returns the method that worked.
+pub async fn set_idle_hint(
+  session: &Login1SessionProxy<'_>,
+  idle: bool,
+  method: HintMethod,
+) -> zbus::Result<HintMethod> {
+  if method == HintMethod::SetIdleHint {
+    match session.set_idle_hint(idle).await {
+      Ok(()) => return Ok(HintMethod::SetIdleHint),
+      Err(e) if is_unknown_method(&e) => {
+        warn!("logind doesn't know SetIdleHint, setting IdleHint instead: {e}")
+      }
+      Err(e) => return Err(e),
+    }
+  }
+  session.inner().set_property("IdleHint", idle).await?;
+  Ok(HintMethod::Properties)
+}
+
+/// The X display whose session is synced: the first one given, or
+/// `$DISPLAY`.
+pub fn monitored_display(displays: &[String]) -> Option<String> {
+  match displays.first() {
+    Some(display) => Some(display.clone()),
+    None => std::env::var("DISPLAY").ok(),
+  }
+}
+
+/// Like [`resolve_session`], for when there is no D-Bus connection yet.
+pub async fn resolve_session_path(x_display: Option<String>) -> String {
+  match Connection::system().await {
+    Ok(conn) => resolve_session(&conn, x_display.as_deref()).await,
+    Err(e) => {
+      warn!("Failed to connect to system D-Bus: {e}");
+      SELF_SESSION_PATH.to_string()
+    }
+  }
+}
+
+async fn find_display_sessions(
+  conn: &Connection,
+  display: &str,
+) -> Result<Vec<(String, OwnedObjectPath)>> {
+  let uid = std::fs::metadata("/proc/self")
+    .context("Failed to determine own uid")?
+    .uid();
+  let manager = Login1ManagerProxy::new(conn).await?;
+  let sessions = manager
+    .list_sessions()
+    .await
+    .context("Failed to list sessions")?;
+
+  let mut matches = vec![];
+  for (id, session_uid, _user, _seat, path) in sessions {
+    if session_uid != uid {
+      continue;
+    }
+
+    let session = Login1SessionProxy::builder(conn)
+      .path(path.clone())?
+      .build()
+      .await?;
+    let session_display = session
+      .display()
+      .await
+      .with_context(|| format!("Failed to get display of session {id}"))?;
+    if same_display(&session_display, display) {
+      matches.push((id, path));
+    }
+  }
+
+  Ok(matches)
+}
+
+/// Compare X display names, ignoring the screen number (":0" == ":0.0").
+fn same_display(a: &str, b: &str) -> bool {
+  fn strip_screen(display: &str) -> &str {
+    match display.rsplit_once(':') {
+      Some((host, num)) => {
+        let num = num.split_once('.').map_or(num, |(num, _)| num);
+        &display[..host.len() + 1 + num.len()]
+      }
+      None => display,
+    }
+  }
+
+  !a.is_empty() && strip_screen(a) == strip_screen(b)
+}
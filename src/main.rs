@@ -1,190 +1,901 @@
+mod activity;
+mod adaptive;
+mod audit;
+mod backoff;
+mod bench;
+mod config;
+mod exit;
+#[cfg(feature = "health")]
+mod health;
+mod history;
+mod hooks;
+mod instance;
+mod keep_awake;
+#[cfg(feature = "libinput")]
+mod libinput;
+mod logging;
+mod logind;
+mod monitor;
+mod network;
+mod schedule;
+mod screensaver;
+mod simulate;
+mod source;
+mod state_file;
+#[cfg(feature = "metrics")]
+mod statsd;
+mod summary;
+mod supervisor;
+mod suspend;
+#[cfg(feature = "tui")]
+mod tui;
+mod x11;
+#[cfg(feature = "xinput2")]
+mod xinput;
+
 use anyhow::{Context, Result};
-use clap::Parser;
-use std::time::Duration;
-use tokio::time::sleep;
-use xcb::{
-  screensaver,
-  x::{Drawable, Window},
-  Connection,
+use clap::{error::ErrorKind, CommandFactory, Parser};
+use std::{path::PathBuf, process::ExitCode, sync::Arc, time::Duration};
+use tokio::{
+  signal::unix::{signal, SignalKind},
+  sync::{mpsc, Notify},
+};
+use tracing::{error, info, warn};
+
+use crate::{
+  audit::AuditLog,
+  backoff::RetryConfig,
+  bench::BenchFormat,
+  config::{ConfigFormat, EffectiveConfig},
+  exit::Fatal,
+  history::History,
+  logging::LogLevel,
+  monitor::{Config, IdleMonitor, Mode, Thresholds},
+  network::NetworkSpec,
+  schedule::TimeRange,
+  source::{SourceSettings, SourceSpec},
+  state_file::{StateFile, StateFormat},
+  suspend::SleepHooks,
+  x11::XOptions,
 };
-use zbus::{proxy, Connection as ZbusConnection};
 
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, after_help = exit::HELP)]
 struct Args {
   /// Idle threshold in seconds
   #[arg(short = 't', long, default_value_t = 300)]
   idle_threshold: u64,
 
-  /// Disable resetting idle hint to false on exit
-  #[arg(short = 'N', long)]
-  no_reset_on_exit: bool,
+  /// Idle time in seconds after which the user becomes idle (defaults to
+  /// the idle threshold)
+  #[arg(long, value_name = "SECONDS")]
+  idle_enter: Option<u64>,
+
+  /// Idle time in seconds below which an idle user becomes active again
+  /// (defaults to the idle threshold)
+  #[arg(long, value_name = "SECONDS")]
+  idle_exit: Option<u64>,
+
+  /// Never write an idle hint on shutdown, in any mode, e.g. when a wrapper
+  /// manages the hint (formerly --no-reset-on-exit)
+  #[arg(short = 'N', long, alias = "no-reset-on-exit")]
+  no_exit_hint: bool,
 
   /// Run as a one-shot idle check (check once and exit)
-  #[arg(short = '1', long)]
+  #[arg(short = '1', long, conflicts_with = "mode")]
   one_shot: bool,
-}
 
-#[proxy(
-  interface = "org.freedesktop.login1.Session",
-  default_service = "org.freedesktop.login1"
-)]
-trait Login1Session {
-  fn set_idle_hint(&self, idle: bool) -> zbus::Result<()>;
-}
+  /// Keep the session active instead of syncing the idle hint: hold an
+  /// idle inhibitor lock and keep resetting the hint until interrupted
+  #[arg(
+    long,
+    conflicts_with_all = ["mode", "one_shot", "print_idle", "list_sources"]
+  )]
+  keep_awake: bool,
 
-struct IdleMonitor {
-  conn: Connection,
-  root: Window,
-  idle_threshold: Duration,
-  check_interval: Duration,
-  session_proxy: Login1SessionProxy<'static>,
-}
+  /// Operating mode
+  #[arg(short = 'm', long, value_enum, default_value_t = Mode::Sync)]
+  mode: Mode,
 
-impl IdleMonitor {
-  async fn new(idle_threshold: Duration) -> Result<Self> {
-    let (conn, screen_num) =
-      Connection::connect(None).context("Failed to connect to X11 server")?;
-    let setup = conn.get_setup();
-    let screen = setup
-      .roots()
-      .nth(screen_num as usize)
-      .context("Failed to get X11 screen")?;
-    let root = screen.root();
-
-    let zbus_conn = ZbusConnection::system()
-      .await
-      .context("Failed to connect to system D-Bus")?;
-    let session_proxy = Login1SessionProxy::builder(&zbus_conn)
-      .path("/org/freedesktop/login1/session/self")?
-      .build()
-      .await
-      .context("Failed to create Login1Session proxy")?;
-
-    let check_interval = (idle_threshold / 10).max(Duration::from_secs(5));
+  /// Exit after running for this many seconds
+  #[arg(long, value_name = "SECONDS", conflicts_with = "one_shot")]
+  max_runtime: Option<u64>,
 
-    Ok(Self {
-      conn,
-      root,
-      idle_threshold,
-      check_interval,
-      session_proxy,
-    })
-  }
+  /// Poll exactly every this many seconds instead of at a tenth of the idle
+  /// threshold (at least 5 seconds)
+  #[arg(
+    long,
+    value_name = "SECONDS",
+    value_parser = clap::value_parser!(u64).range(1..)
+  )]
+  fixed_interval: Option<u64>,
 
-  fn get_idle_duration(&self) -> Result<Duration> {
-    let cookie = self.conn.send_request(&screensaver::QueryInfo {
-      drawable: Drawable::Window(self.root),
-    });
-    let reply = self
-      .conn
-      .wait_for_reply(cookie)
-      .context("Failed to get screensaver info")?;
-    let idle_ms = reply.ms_since_user_input();
-    Ok(Duration::from_millis(idle_ms as u64))
-  }
+  /// Poll on multiples of the interval since the Unix epoch, e.g. every 10
+  /// seconds on the 10-second mark, so that logs line up across machines
+  #[arg(long, conflicts_with = "one_shot")]
+  align_polls: bool,
 
-  async fn run(&self, mut signals: ExitSignals) -> Result<()> {
-    let mut state = false;
+  /// Only push the idle hint when the user becomes idle and never reset it
+  /// to false while running (one-way sync)
+  #[arg(long, conflicts_with = "mode")]
+  set_idle_only: bool,
 
-    loop {
-      tokio::select! {
-        _ = signals.recv() => {
-          println!("Received shutdown signal, exiting idle monitoring loop...");
-          break;
-        }
+  /// Daily local time range like 18:00-23:30 during which the user is never
+  /// considered idle, may be given multiple times
+  #[arg(long, value_name = "HH:MM-HH:MM", conflicts_with = "mode")]
+  quiet_hours: Vec<TimeRange>,
 
-        _ = sleep(self.check_interval) => {
-          let idle = self.get_idle_duration()?;
-          let new_state = idle >= self.idle_threshold;
-          self.session_proxy.set_idle_hint(new_state)
-            .await
-            .context("Failed to set idle hint")?;
+  /// Only consider the user idle once the idle counter has been rising
+  /// continuously up to the threshold, ignoring sudden jumps
+  #[arg(long, conflicts_with = "mode")]
+  require_continuous_idle: bool,
 
-          if new_state != state {
-            println!("User is {}", if new_state { "idle" } else { "active" });
-          }
+  /// Decide on the median idle time of the last K readings to reduce
+  /// flapping near the threshold
+  #[arg(
+    long,
+    value_name = "K",
+    default_value_t = 1,
+    value_parser = clap::value_parser!(u32).range(1..),
+    conflicts_with = "mode"
+  )]
+  smooth_window: u32,
 
-          state = new_state;
-        }
-      }
-    }
+  /// Experimental: adapt the idle threshold to how long the user usually
+  /// stays active and idle, within MIN-MAX seconds
+  #[arg(
+    long,
+    value_name = "MIN-MAX",
+    conflicts_with_all = ["mode", "one_shot"]
+  )]
+  adaptive_threshold: Option<adaptive::Band>,
 
-    Ok(())
-  }
+  /// Act on at most this many seconds of idle time, however large the
+  /// reading. Must not be less than the idle threshold.
+  #[arg(long, value_name = "SECONDS")]
+  max_reported_idle: Option<u64>,
+
+  /// Turn the screen off with DPMS when the user becomes idle, and back on
+  /// when they become active
+  #[arg(long, conflicts_with_all = ["mode", "one_shot"])]
+  dpms_off_on_idle: bool,
+
+  /// Also follow the desktop's org.freedesktop.ScreenSaver ActiveChanged
+  /// signal, marking the user idle as soon as the screensaver activates
+  #[arg(long, conflicts_with_all = ["mode", "one_shot"])]
+  follow_screensaver: bool,
+
+  /// After every poll, compare logind's idle hint and idle time with our own
+  /// and log discrepancies caused by other idle providers
+  #[arg(long, conflicts_with_all = ["mode", "one_shot"])]
+  cross_check: bool,
+
+  /// Every this many seconds, read back the idle hint from logind and
+  /// re-assert our own state if another component changed it
+  #[arg(long, value_name = "SECONDS", conflicts_with_all = ["mode", "one_shot"])]
+  reassert_interval: Option<u64>,
+
+  /// Shell command to run when the user becomes idle
+  #[arg(long, value_name = "CMD", conflicts_with = "one_shot")]
+  on_idle: Option<String>,
+
+  /// Shell command to run when the user becomes active
+  #[arg(long, value_name = "CMD", conflicts_with = "one_shot")]
+  on_active: Option<String>,
+
+  /// Shell command to run when the system is about to suspend
+  #[arg(long, value_name = "CMD", conflicts_with = "one_shot")]
+  on_suspend: Option<String>,
+
+  /// Shell command to run when the system resumed from suspend
+  #[arg(long, value_name = "CMD", conflicts_with = "one_shot")]
+  on_resume: Option<String>,
+
+  /// Delay suspend while the --on-suspend hook runs, for at most this many
+  /// seconds
+  #[arg(long, value_name = "SECONDS", requires = "on_suspend")]
+  suspend_hook_timeout: Option<u64>,
+
+  /// Shell command to run on a graceful shutdown, before the idle hint is
+  /// reset
+  #[arg(long, value_name = "CMD", conflicts_with = "one_shot")]
+  on_exit: Option<String>,
+
+  /// Stop waiting for the --on-exit hook after this many seconds
+  #[arg(
+    long,
+    value_name = "SECONDS",
+    default_value_t = 10,
+    value_parser = clap::value_parser!(u64).range(1..)
+  )]
+  on_exit_timeout: u64,
+
+  /// Write logs to this file instead of stderr, reopening it on SIGHUP
+  #[arg(long, value_name = "PATH")]
+  log_file: Option<PathBuf>,
+
+  /// Append a JSON record of every idle state transition to this file,
+  /// synced to disk after each write
+  #[arg(long, value_name = "PATH", conflicts_with = "one_shot")]
+  audit_file: Option<PathBuf>,
+
+  /// Keep this many recent idle state transitions and serve them with the
+  /// GetRecentTransitions method on the session bus
+  #[arg(
+    long,
+    value_name = "N",
+    value_parser = clap::value_parser!(u32).range(1..),
+    conflicts_with_all = ["one_shot", "print_idle", "list_sources"]
+  )]
+  history_size: Option<u32>,
+
+  /// On exit, write a JSON summary of the run to this file, or to stdout
+  /// for `-`
+  #[arg(
+    long,
+    value_name = "PATH",
+    conflicts_with_all = ["print_idle", "list_sources"]
+  )]
+  summary_json: Option<PathBuf>,
+
+  /// Keep the current idle state in this file for status bars, replacing
+  /// it atomically on every transition and removing it on exit
+  #[arg(long, value_name = "PATH", conflicts_with_all = ["mode", "one_shot"])]
+  state_file: Option<PathBuf>,
+
+  /// Format of --state-file
+  #[arg(
+    long,
+    value_name = "FORMAT",
+    value_enum,
+    default_value_t,
+    requires = "state_file"
+  )]
+  state_format: StateFormat,
+
+  /// Log level
+  #[arg(long, value_enum, default_value_t = LogLevel::Info)]
+  log_level: LogLevel,
+
+  /// Only log warnings and errors
+  #[arg(short, long, conflicts_with = "log_level")]
+  quiet: bool,
+
+  /// Serve an HTTP health check endpoint at /healthz on this address, or
+  /// on the first free port of a range like 127.0.0.1:9184-9199. A socket
+  /// passed by systemd socket activation takes precedence.
+  #[cfg(feature = "health")]
+  #[arg(long, value_name = "ADDR")]
+  health_addr: Option<health::HealthAddr>,
+
+  /// Push metrics to the statsd collector at this address, like
+  /// localhost:8125, over UDP
+  #[cfg(feature = "metrics")]
+  #[arg(long, value_name = "HOST:PORT", conflicts_with = "one_shot")]
+  statsd_addr: Option<String>,
+
+  /// Show a live status view instead of the log while stdout is a terminal
+  #[cfg(feature = "tui")]
+  #[arg(
+    long,
+    conflicts_with_all = [
+      "one_shot", "print_idle", "list_sources", "keep_awake", "bench",
+      "explain", "simulate_input"
+    ]
+  )]
+  tui: bool,
+
+  /// Where to read the idle time from: `screensaver` (the X11 screensaver
+  /// extension), `fifo:<path>` (idle milliseconds written to a named pipe
+  /// or file, one value per line) or, if built in, `libinput` (the seat's
+  /// input devices) or `xinput2` (XInput2 raw input events)
+  #[arg(long, value_name = "SOURCE", default_value = "screensaver")]
+  idle_source: SourceSpec,
+
+  /// Input device whose events don't count as activity with the libinput
+  /// source, by name as in /proc/bus/input/devices, may be given multiple
+  /// times
+  #[cfg(feature = "libinput")]
+  #[arg(long, value_name = "NAME")]
+  ignore_device: Vec<String>,
+
+  /// X display to monitor with the screensaver source, may be given
+  /// multiple times (defaults to $DISPLAY). The user counts as idle only
+  /// when idle on all displays.
+  #[arg(long = "display", value_name = "DISPLAY")]
+  displays: Vec<String>,
+
+  /// Seconds allowed for connecting to an X display on another host, like
+  /// host:0
+  #[arg(
+    long,
+    value_name = "SECONDS",
+    default_value_t = 10,
+    value_parser = clap::value_parser!(u64).range(1..)
+  )]
+  x_connect_timeout: u64,
+
+  /// Seconds an X display on another host may take to answer before it is
+  /// considered lost and reconnected
+  #[arg(
+    long,
+    value_name = "SECONDS",
+    default_value_t = 30,
+    value_parser = clap::value_parser!(u64).range(1..)
+  )]
+  x_reply_timeout: u64,
+
+  /// Also count pointer motion as activity, for remote desktop setups that
+  /// move the pointer without resetting the screensaver
+  #[arg(long)]
+  watch_pointer: bool,
+
+  /// At startup, check that the X idle counter advances over a second
+  #[arg(long)]
+  verify_counter: bool,
+
+  /// Exit instead of warning when --verify-counter finds the counter stuck
+  #[arg(long, requires = "verify_counter")]
+  strict: bool,
+
+  /// Also count traffic on a network interface as activity once it reaches
+  /// this many bytes between two polls, may be given multiple times
+  #[arg(long, value_name = "IFACE:MIN_BYTES", conflicts_with = "one_shot")]
+  watch_network: Vec<NetworkSpec>,
+
+  /// Refuse to start if the X server's MIT-SCREEN-SAVER extension is
+  /// older than known to work, instead of only warning
+  #[arg(long)]
+  strict_extension: bool,
+
+  /// Fail D-Bus calls that need authorization if polkit doesn't decide
+  /// quickly, rather than waiting for it
+  #[arg(long)]
+  non_interactive: bool,
+
+  /// Keep monitoring when the system bus or logind is unavailable, and
+  /// send the idle hint once they can be reached
+  #[arg(long)]
+  allow_no_dbus: bool,
+
+  /// Keep monitoring without setting the idle hint when logind refuses it,
+  /// instead of exiting with status 12
+  #[arg(long)]
+  ignore_permission_errors: bool,
+
+  /// Wait this many seconds before connecting to D-Bus at startup, for
+  /// display managers that start us before the session is registered
+  #[arg(long, value_name = "SECONDS")]
+  dbus_connect_delay: Option<u64>,
+
+  /// Delay in seconds before the first retry of a failed connection
+  #[arg(long, value_name = "SECONDS", default_value_t = 1)]
+  retry_initial_delay: u64,
+
+  /// Upper bound in seconds for the delay between retries
+  #[arg(long, value_name = "SECONDS", default_value_t = 60)]
+  retry_max_delay: u64,
+
+  /// Factor by which the retry delay grows after each failed attempt
+  #[arg(long, value_name = "FACTOR", default_value_t = 2.0)]
+  retry_multiplier: f64,
+
+  /// Number of connection attempts before giving up (0 retries forever)
+  #[arg(long, value_name = "N", default_value_t = 1)]
+  retry_max_attempts: u32,
+
+  /// Print the effective configuration and exit
+  #[arg(
+    long,
+    value_name = "FORMAT",
+    value_enum,
+    num_args = 0..=1,
+    default_missing_value = "toml"
+  )]
+  print_config: Option<ConfigFormat>,
+
+  /// Run the poll loop for this many seconds without D-Bus, then report
+  /// statistics of the poll timing and X reply latency
+  #[arg(
+    long,
+    value_name = "SECONDS",
+    value_parser = clap::value_parser!(u64).range(1..),
+    conflicts_with_all = ["one_shot", "print_idle", "list_sources", "keep_awake"]
+  )]
+  bench: Option<u64>,
+
+  /// Format of the --bench report
+  #[arg(
+    long,
+    value_name = "FORMAT",
+    value_enum,
+    default_value_t,
+    requires = "bench"
+  )]
+  bench_format: BenchFormat,
+
+  /// Inject a key press with XTEST, check that it resets the X idle
+  /// counter and exit. The key has no symbols, so no client acts on it.
+  #[arg(
+    long,
+    conflicts_with_all = [
+      "one_shot", "print_idle", "list_sources", "keep_awake", "bench"
+    ]
+  )]
+  simulate_input: bool,
+
+  /// Print the raw idle time in milliseconds at every poll without
+  /// touching the idle hint
+  #[arg(long, conflicts_with_all = ["mode", "one_shot"])]
+  print_idle: bool,
+
+  /// Print a one-line rationale of every decision: the idle time, the
+  /// threshold and what overrode it
+  #[arg(long, conflicts_with = "mode")]
+  explain: bool,
+
+  /// Poll and sync the idle hint right away on SIGUSR1, e.g. after external
+  /// state changes
+  #[arg(long, conflicts_with = "one_shot")]
+  poll_immediately_on_signal: bool,
+
+  /// Make a running instance for the same display quit instead of exiting
+  #[arg(long, conflicts_with = "one_shot")]
+  takeover: bool,
+
+  /// Probe every idle source, print whether it is available and its
+  /// current reading, and exit
+  #[arg(long, conflicts_with_all = ["print_idle", "one_shot"])]
+  list_sources: bool,
+
+  /// Print --print-idle readings as JSON
+  #[arg(long, requires = "print_idle")]
+  json: bool,
+
+  /// Add the raw MIT-SCREEN-SAVER state of every display to --json
+  /// readings
+  #[arg(long, requires = "json")]
+  query_info: bool,
+
+  /// TOML file with defaults for the options above, keyed by their long
+  /// names. Options given on the command line take precedence.
+  #[arg(long, value_name = "PATH")]
+  config: Option<PathBuf>,
+
+  /// Apply the settings of this profile from the config file's [profiles]
+  /// table, overriding its top-level settings
+  #[arg(long, value_name = "NAME", requires = "config")]
+  profile: Option<String>,
+}
 
-  async fn one_shot_check(&self) -> Result<()> {
-    let idle = self.get_idle_duration()?;
-    let state = idle >= self.idle_threshold;
-    self
-      .session_proxy
-      .set_idle_hint(state)
-      .await
-      .context("Failed to set idle hint")?;
-    println!("User is {}", if state { "idle" } else { "active" });
-    Ok(())
+fn source_settings(args: &Args) -> SourceSettings {
+  SourceSettings {
+    spec: args.idle_source.clone(),
+    displays: args.displays.clone(),
+    x11: XOptions {
+      watch_pointer: args.watch_pointer,
+      strict_extension: args.strict_extension,
+      verify_counter: args.verify_counter,
+      strict_counter: args.strict,
+      dpms: args.dpms_off_on_idle,
+      connect_timeout: Duration::from_secs(args.x_connect_timeout),
+      reply_timeout: Duration::from_secs(args.x_reply_timeout),
+    },
+    #[cfg(feature = "libinput")]
+    ignore_devices: args.ignore_device.clone(),
   }
+}
 
-  async fn set_idle_hint_false(&self) -> Result<()> {
-    self
-      .session_proxy
-      .set_idle_hint(false)
-      .await
-      .context("Failed to set idle hint to false")?;
-    Ok(())
+/// Why no idle hint should be written on shutdown, if it shouldn't. This is
+/// the only place deciding on the exit hint.
+fn skip_exit_hint(args: &Args) -> Option<&'static str> {
+  if args.no_exit_hint {
+    Some("--no-exit-hint")
+  } else if args.one_shot {
+    // The check's own hint is the result
+    Some("one-shot check")
+  } else {
+    None
   }
 }
 
+/// Re-read the command line and config file whenever SIGHUP is received,
+/// passing the idle source settings on to `tx`.
+fn reload_on_sighup(tx: mpsc::Sender<SourceSettings>) -> Result<()> {
+  let mut sighup =
+    signal(SignalKind::hangup()).context("Failed to install SIGHUP handler")?;
+  tokio::spawn(async move {
+    while sighup.recv().await.is_some() {
+      let args = match config::try_parse_args() {
+        Ok((args, _)) => args,
+        Err(e) => {
+          error!("Failed to reload config, keeping the current one: {e:#}");
+          continue;
+        }
+      };
+      if tx.send(source_settings(&args)).await.is_err() {
+        break;
+      }
+    }
+  });
+  Ok(())
+}
+
+/// Ask for an immediate poll whenever SIGUSR1 is received.
+fn refresh_on_sigusr1(tx: mpsc::Sender<()>) -> Result<()> {
+  let mut sigusr1 = signal(SignalKind::user_defined1())
+    .context("Failed to install SIGUSR1 handler")?;
+  tokio::spawn(async move {
+    while sigusr1.recv().await.is_some() {
+      // A refresh that is already pending covers this one too
+      if let Err(mpsc::error::TrySendError::Closed(())) = tx.try_send(()) {
+        break;
+      }
+    }
+  });
+  Ok(())
+}
+
 pub struct ExitSignals {
   sigint: tokio::signal::unix::Signal,
   sigterm: tokio::signal::unix::Signal,
+  quit: Arc<Notify>,
 }
 
 impl ExitSignals {
   pub fn new() -> Result<Self> {
-    use tokio::signal::unix::{signal, SignalKind};
     let sigterm = signal(SignalKind::terminate())
       .context("Failed to install SIGTERM handler")?;
     let sigint = signal(SignalKind::interrupt())
       .context("Failed to install SIGINT handler")?;
-    Ok(Self { sigint, sigterm })
+    Ok(Self {
+      sigint,
+      sigterm,
+      quit: Arc::default(),
+    })
+  }
+
+  /// Handle to request an exit as if a signal had arrived.
+  pub fn quit(&self) -> Arc<Notify> {
+    self.quit.clone()
   }
 
   pub async fn recv(&mut self) {
     tokio::select! {
       _ = self.sigterm.recv() => {}
       _ = self.sigint.recv() => {}
+      _ = self.quit.notified() => {}
     }
   }
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-  let args = Args::parse();
+fn main() -> ExitCode {
+  // Before the runtime starts any threads, see health::Activated::take
+  #[cfg(feature = "health")]
+  let run = run(health::Activated::take());
+  #[cfg(not(feature = "health"))]
+  let run = run();
+
+  let result = tokio::runtime::Runtime::new()
+    .context("Failed to start the async runtime")
+    .and_then(|runtime| runtime.block_on(run));
+  match result {
+    Ok(()) => ExitCode::SUCCESS,
+    Err(e) => {
+      eprintln!("Error: {e:?}");
+      exit::code(&e)
+    }
+  }
+}
+
+async fn run(
+  #[cfg(feature = "health")] activated: Option<health::Activated>,
+) -> Result<()> {
+  let (args, config_file) = config::parse_args();
+  let log_level = if args.quiet {
+    LogLevel::Warn
+  } else {
+    args.log_level
+  };
+  let log_output = logging::init(args.log_file.as_deref(), log_level)
+    .context(Fatal::Config)?;
+  log_output.reopen_on_sighup()?;
+  if let Some(profile) = &config_file.profile {
+    info!("Using config profile {profile}");
+  }
   let exit_signals = ExitSignals::new()?;
-  let idle_threshold = Duration::from_secs(args.idle_threshold);
+  let audit = match &args.audit_file {
+    Some(path) => {
+      let audit = AuditLog::open(path).context(Fatal::Config)?;
+      audit.reopen_on_sighup()?;
+      Some(audit)
+    }
+    None => None,
+  };
+  // The band only applies to the global threshold, sessions have their own
+  let thresholds = {
+    let enter = args.idle_enter.unwrap_or(args.idle_threshold);
+    let exit = args.idle_exit.unwrap_or(args.idle_threshold);
+    if exit > enter {
+      Args::command()
+        .error(
+          ErrorKind::ArgumentConflict,
+          "--idle-exit must not be greater than --idle-enter",
+        )
+        .exit();
+    }
+    if args.max_reported_idle.is_some_and(|max| max < enter) {
+      Args::command()
+        .error(
+          ErrorKind::ArgumentConflict,
+          "--max-reported-idle must not be less than the idle threshold",
+        )
+        .exit();
+    }
+    Thresholds {
+      enter: Duration::from_secs(enter),
+      exit: Duration::from_secs(exit),
+    }
+  };
+  if let (Some(band), Some(max)) =
+    (args.adaptive_threshold, args.max_reported_idle)
+  {
+    if band.max() > Duration::from_secs(max) {
+      Args::command()
+        .error(
+          ErrorKind::ArgumentConflict,
+          "--max-reported-idle must not be less than the top of \
+           --adaptive-threshold",
+        )
+        .exit();
+    }
+  }
+  if args.retry_multiplier.is_nan() || args.retry_multiplier < 1.0 {
+    Args::command()
+      .error(
+        ErrorKind::ValueValidation,
+        "--retry-multiplier must be at least 1",
+      )
+      .exit();
+  }
+  if args.query_info && args.idle_source != SourceSpec::Screensaver {
+    Args::command()
+      .error(
+        ErrorKind::ArgumentConflict,
+        "--query-info requires --idle-source screensaver",
+      )
+      .exit();
+  }
+  // Only the screensaver source has an X connection to force DPMS on
+  if args.dpms_off_on_idle && args.idle_source != SourceSpec::Screensaver {
+    Args::command()
+      .error(
+        ErrorKind::ArgumentConflict,
+        "--dpms-off-on-idle requires --idle-source screensaver",
+      )
+      .exit();
+  }
+  #[cfg(feature = "libinput")]
+  if !args.ignore_device.is_empty() && args.idle_source != SourceSpec::Libinput
+  {
+    Args::command()
+      .error(
+        ErrorKind::ArgumentConflict,
+        "--ignore-device requires --idle-source libinput",
+      )
+      .exit();
+  }
+  let retry = RetryConfig {
+    initial_delay: Duration::from_secs(args.retry_initial_delay),
+    max_delay: Duration::from_secs(args.retry_max_delay),
+    multiplier: args.retry_multiplier,
+    max_attempts: args.retry_max_attempts,
+  };
+
+  let config = Config {
+    thresholds,
+    session_thresholds: config_file
+      .sessions
+      .iter()
+      .map(|(name, &threshold)| {
+        let thresholds =
+          Thresholds::without_band(Duration::from_secs(threshold));
+        (name.clone(), thresholds)
+      })
+      .collect(),
+    adaptive_threshold: args.adaptive_threshold,
+    mode: args.mode,
+    set_idle_only: args.set_idle_only,
+    max_runtime: args.max_runtime.map(Duration::from_secs),
+    fixed_interval: args.fixed_interval.map(Duration::from_secs),
+    align_polls: args.align_polls,
+    reassert_interval: args.reassert_interval.map(Duration::from_secs),
+    quiet_hours: args.quiet_hours.clone(),
+    require_continuous_idle: args.require_continuous_idle,
+    smooth_window: args.smooth_window as usize,
+    max_reported_idle: args.max_reported_idle.map(Duration::from_secs),
+    source: source_settings(&args),
+    follow_screensaver: args.follow_screensaver,
+    watch_networks: args.watch_network.clone(),
+    audit,
+    history: args.history_size.map(|size| History::new(size as usize)),
+    #[cfg(feature = "metrics")]
+    statsd: args
+      .statsd_addr
+      .as_deref()
+      .map(statsd::Statsd::connect)
+      .transpose()
+      .context(Fatal::Config)?,
+    state_file: args
+      .state_file
+      .as_deref()
+      .map(|path| StateFile::new(path, args.state_format)),
+    on_idle: args.on_idle.clone(),
+    on_active: args.on_active.clone(),
+    explain: args.explain,
+    cross_check: args.cross_check,
+    allow_no_dbus: args.allow_no_dbus,
+    ignore_permission_errors: args.ignore_permission_errors,
+    dbus_connect_delay: args.dbus_connect_delay.map(Duration::from_secs),
+    retry,
+  };
 
-  let idle_monitor = IdleMonitor::new(idle_threshold)
+  if let Some(format) = args.print_config {
+    let x_display = config.source.x_display();
+    let session_path = logind::resolve_session_path(x_display).await;
+    EffectiveConfig::new(&args, &config, log_level, session_path)
+      .print(format)?;
+    return Ok(());
+  }
+
+  if args.list_sources {
+    source::list_sources(&config.source).await;
+    return Ok(());
+  }
+
+  if args.print_idle {
+    return monitor::print_idle(
+      &config,
+      exit_signals,
+      args.json,
+      args.query_info,
+    )
+    .await;
+  }
+
+  if let Some(duration) = args.bench {
+    return bench::run(
+      &config,
+      Duration::from_secs(duration),
+      args.bench_format,
+      exit_signals,
+    )
+    .await;
+  }
+
+  if args.simulate_input {
+    return simulate::run(&config).await;
+  }
+
+  // One-shot checks may well run next to the daemon
+  if !args.one_shot {
+    let x_display = logind::monitored_display(&args.displays);
+    instance::acquire(x_display.as_deref(), args.takeover, exit_signals.quit())
+      .await?;
+  }
+
+  if args.keep_awake {
+    return keep_awake::run(
+      config.source.x_display(),
+      monitor::check_interval(&config),
+      args.non_interactive,
+      exit_signals,
+    )
+    .await;
+  }
+
+  let mut idle_monitor = IdleMonitor::new(config.clone())
     .await
     .context("Failed to create IdleMonitor")?;
 
-  println!(
-    "x11-idle-sync started with idle threshold of {} seconds",
-    args.idle_threshold
-  );
+  if args.on_suspend.is_some() || args.on_resume.is_some() {
+    let hooks = SleepHooks {
+      on_suspend: args.on_suspend.clone(),
+      on_resume: args.on_resume.clone(),
+      suspend_timeout: args.suspend_hook_timeout.map(Duration::from_secs),
+      non_interactive: args.non_interactive,
+    };
+    let status = idle_monitor.subscribe();
+    let backend = config.source.spec.to_string();
+    match idle_monitor.dbus_connection() {
+      Some(conn) => suspend::spawn(conn, hooks, status, backend).await?,
+      None => warn!("Suspend hooks are disabled without D-Bus"),
+    }
+  }
+
+  // Dropping the connection would stop the service
+  let _history_service = match &config.history {
+    Some(history) => match history::serve(history.clone()).await {
+      Ok(conn) => Some(conn),
+      Err(e) => {
+        warn!("Not serving the transition history: {e:#}");
+        None
+      }
+    },
+    None => None,
+  };
+
+  #[cfg(feature = "health")]
+  if let Some(listener) =
+    health::Listener::new(args.health_addr.as_ref(), activated).await?
+  {
+    let max_age = idle_monitor.check_interval() * 2;
+    health::spawn(listener, idle_monitor.subscribe(), max_age);
+  }
+
+  // Started early so that the startup messages end up in the view
+  #[cfg(feature = "tui")]
+  let status_view = if args.tui {
+    tui::StatusView::start(idle_monitor.subscribe(), log_output.clone())
+  } else {
+    None
+  };
+
+  let Thresholds { enter, exit } = idle_monitor.thresholds();
+  match args.mode {
+    Mode::Sync if enter == exit => info!(
+      "x11-idle-sync started with idle threshold of {} seconds",
+      enter.as_secs()
+    ),
+    Mode::Sync => info!(
+      "x11-idle-sync started with idle thresholds of {} seconds (enter) \
+       and {} seconds (exit)",
+      enter.as_secs(),
+      exit.as_secs()
+    ),
+    Mode::ClearOnActivity => {
+      info!("x11-idle-sync started in clear-on-activity mode")
+    }
+  }
 
   if args.one_shot {
     idle_monitor.one_shot_check().await?;
   } else {
-    idle_monitor.run(exit_signals).await?;
+    let (reload_tx, reload_rx) = mpsc::channel(1);
+    reload_on_sighup(reload_tx)?;
+    let (refresh_tx, refresh_rx) = mpsc::channel(1);
+    if args.poll_immediately_on_signal {
+      refresh_on_sigusr1(refresh_tx)?;
+    }
+    idle_monitor
+      .run(exit_signals, reload_rx, refresh_rx)
+      .await?;
   }
+  #[cfg(feature = "tui")]
+  drop(status_view);
 
-  // Set idle hint to false before exiting, unless disabled
-  if !args.no_reset_on_exit && !args.one_shot {
-    idle_monitor.set_idle_hint_false().await?;
-    println!("Idle hint set to false. Exiting.");
-  } else {
-    println!("Exiting without resetting idle hint.");
+  // Only reached once, and only when shutting down gracefully
+  if let Some(cmd) = &args.on_exit {
+    let limit = Duration::from_secs(args.on_exit_timeout);
+    hooks::run("exit", cmd, Some(limit), &idle_monitor.hook_env()).await;
+  }
+
+  match skip_exit_hint(&args) {
+    Some(why) => info!("Exiting without an exit hint ({why})."),
+    None if idle_monitor.set_idle_hint_false().await? => {
+      info!("Idle hint set to false. Exiting.")
+    }
+    None => {
+      info!("Idle hint not reset, logind is unreachable or refused it.")
+    }
+  }
+
+  if let Some(state_file) = &config.state_file {
+    state_file.remove();
+  }
+
+  if let Some(path) = &args.summary_json {
+    idle_monitor.summary().write(path)?;
   }
 
   Ok(())
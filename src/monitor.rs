@@ -0,0 +1,1523 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Local};
+use clap::ValueEnum;
+use futures_util::StreamExt;
+use serde::Serialize;
+use std::{
+  collections::{BTreeMap, VecDeque},
+  fmt,
+  time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+  sync::{mpsc, watch},
+  time::{interval, interval_at, sleep, Interval, MissedTickBehavior},
+};
+use tracing::{debug, error, info, warn};
+use zbus::{proxy::CacheProperties, Connection as ZbusConnection};
+
+#[cfg(feature = "metrics")]
+use crate::statsd::Statsd;
+use crate::{
+  activity::Activity,
+  adaptive::{AdaptiveThreshold, Band},
+  audit::AuditLog,
+  backoff::{self, RetryConfig},
+  exit::Fatal,
+  history::History,
+  hooks,
+  logind::{self, HintMethod, Login1SessionProxy},
+  network::{self, NetworkSpec, NetworkWatch},
+  schedule::{self, TimeRange},
+  screensaver::{self, ActiveChangedStream},
+  source::{IdleSource, SourceSettings},
+  state_file::StateFile,
+  summary::{Stats, Summary},
+  x11::{ScreensaverInfo, ScreensaverSource, XOptions},
+  ExitSignals,
+};
+
+#[derive(ValueEnum, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Mode {
+  /// Set the idle hint according to the idle threshold
+  Sync,
+  /// Clear the idle hint on X activity and leave it alone otherwise (the
+  /// idle threshold is ignored)
+  ClearOnActivity,
+}
+
+/// Idle thresholds with a hysteresis band: the user becomes idle once the
+/// idle time reaches `enter` and only becomes active again once it drops
+/// below `exit`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Thresholds {
+  pub enter: Duration,
+  pub exit: Duration,
+}
+
+impl Thresholds {
+  /// Enter and leave idle at the same `threshold`.
+  pub fn without_band(threshold: Duration) -> Self {
+    Self {
+      enter: threshold,
+      exit: threshold,
+    }
+  }
+
+  fn is_idle(&self, was_idle: bool, idle: Duration) -> bool {
+    if was_idle {
+      idle >= self.exit
+    } else {
+      idle >= self.enter
+    }
+  }
+}
+
+/// Why the user was last considered idle or active.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reason {
+  ThresholdExceeded,
+  BelowThreshold,
+  QuietHours,
+  ScreensaverActive,
+  ScreensaverInactive,
+  ActivityDetected,
+  ActivityReported,
+}
+
+impl fmt::Display for Reason {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      Self::ThresholdExceeded => "threshold exceeded",
+      Self::BelowThreshold => "below threshold",
+      Self::QuietHours => "quiet hours",
+      Self::ScreensaverActive => "screensaver active",
+      Self::ScreensaverInactive => "screensaver inactive",
+      Self::ActivityDetected => "activity detected",
+      Self::ActivityReported => "activity reported",
+    })
+  }
+}
+
+/// The inputs and outcome of one decision, formatted as a single line like
+/// `idle_ms=12345 threshold_ms=300000 overrides=[] -> active (below
+/// threshold)`.
+struct Explanation {
+  /// The reading before smoothing and continuity checks
+  raw: Duration,
+  idle: Duration,
+  threshold: Duration,
+  overrides: Vec<&'static str>,
+  idle_state: bool,
+  reason: Reason,
+}
+
+impl fmt::Display for Explanation {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "idle_ms={}", self.idle.as_millis())?;
+    if self.raw != self.idle {
+      write!(f, " raw_ms={}", self.raw.as_millis())?;
+    }
+    write!(
+      f,
+      " threshold_ms={} overrides=[{}] -> {} ({})",
+      self.threshold.as_millis(),
+      self.overrides.join(", "),
+      if self.idle_state { "idle" } else { "active" },
+      self.reason
+    )
+  }
+}
+
+/// Behavior of the monitor, resolved from the command line.
+#[derive(Clone, Debug)]
+pub struct Config {
+  pub thresholds: Thresholds,
+  /// Replacements for `thresholds` by logind session id or seat name,
+  /// without the global band
+  pub session_thresholds: BTreeMap<String, Thresholds>,
+  /// Adapt the thresholds to the user's habits within this band
+  pub adaptive_threshold: Option<Band>,
+  pub mode: Mode,
+  pub set_idle_only: bool,
+  pub max_runtime: Option<Duration>,
+  /// Poll interval overriding the one derived from the mode and threshold
+  pub fixed_interval: Option<Duration>,
+  /// Poll on wall-clock multiples of the interval
+  pub align_polls: bool,
+  pub reassert_interval: Option<Duration>,
+  pub quiet_hours: Vec<TimeRange>,
+  pub require_continuous_idle: bool,
+  /// Number of readings the idle time is smoothed over
+  pub smooth_window: usize,
+  /// Cap on the idle time acted on, against bogus counter values
+  pub max_reported_idle: Option<Duration>,
+  pub source: SourceSettings,
+  pub follow_screensaver: bool,
+  /// Interfaces whose traffic counts as activity
+  pub watch_networks: Vec<NetworkSpec>,
+  pub audit: Option<AuditLog>,
+  /// Recent transitions, for `GetRecentTransitions`
+  pub history: Option<History>,
+  #[cfg(feature = "metrics")]
+  pub statsd: Option<Statsd>,
+  pub state_file: Option<StateFile>,
+  /// Shell commands to run when the user becomes idle or active
+  pub on_idle: Option<String>,
+  pub on_active: Option<String>,
+  /// Print the rationale of every decision
+  pub explain: bool,
+  /// Keep running without logind, sending the hint once it is reachable
+  pub allow_no_dbus: bool,
+  /// Keep monitoring when logind refuses the hint
+  pub ignore_permission_errors: bool,
+  /// Wait this long at startup before connecting to D-Bus
+  pub dbus_connect_delay: Option<Duration>,
+  /// Compare logind's idle state with ours after every poll
+  pub cross_check: bool,
+  pub retry: RetryConfig,
+}
+
+const MIN_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How much longer than our own idle time logind may report the user idle
+/// before `--cross-check` complains.
+const CROSS_CHECK_TOLERANCE: Duration = Duration::from_secs(5);
+
+/// How far the idle counter may run ahead of the wall clock between two
+/// readings before it is considered to have jumped.
+const JUMP_TOLERANCE: Duration = Duration::from_secs(2);
+
+/// Tracks for how long the idle counter has been rising continuously, so
+/// that a glitch making the counter jump ahead isn't taken at face value.
+/// Accumulation starts with the first reading, which could be a glitch
+/// itself.
+#[derive(Default)]
+struct ContinuityCheck {
+  last: Option<(Duration, Instant)>,
+  verified: Duration,
+}
+
+impl ContinuityCheck {
+  /// Feed a new reading taken at `now`, returning the idle time that is
+  /// backed by a continuously rising counter.
+  fn update(&mut self, idle: Duration, now: Instant) -> Duration {
+    self.verified = match self.last {
+      None => Duration::ZERO,
+      Some((last, at)) => {
+        let elapsed = now - at;
+        if idle > last + elapsed + JUMP_TOLERANCE {
+          warn!(
+            "Idle counter jumped from {}ms to {}ms within {}ms, restarting \
+             idle accumulation",
+            last.as_millis(),
+            idle.as_millis(),
+            elapsed.as_millis()
+          );
+          Duration::ZERO
+        } else {
+          // A counter reset (activity) makes the reading drop below the
+          // accumulated time
+          idle.min(self.verified + elapsed)
+        }
+      }
+    };
+    self.last = Some((idle, now));
+    self.verified
+  }
+}
+
+/// Median of the latest readings, so that an idle time jittering around a
+/// threshold doesn't make the state flap.
+struct Smoother {
+  size: usize,
+  readings: VecDeque<Duration>,
+}
+
+impl Smoother {
+  fn new(size: usize) -> Self {
+    Self {
+      size,
+      readings: VecDeque::with_capacity(size),
+    }
+  }
+
+  /// Feed a new reading, returning the median of the window.
+  fn update(&mut self, idle: Duration) -> Duration {
+    if self.readings.len() == self.size {
+      self.readings.pop_front();
+    }
+    self.readings.push_back(idle);
+
+    let mut sorted: Vec<_> = self.readings.iter().copied().collect();
+    sorted.sort_unstable();
+    sorted[sorted.len() / 2]
+  }
+
+  /// Drop all readings, e.g. once they are known to be outdated.
+  fn clear(&mut self) {
+    self.readings.clear();
+  }
+}
+
+/// Outcome of the latest poll, published for status reporting.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Status {
+  pub idle: bool,
+  /// When the user last provided input, while idle
+  pub idle_since: Option<SystemTime>,
+  pub idle_time: Duration,
+  /// Idle time at which the user becomes idle
+  pub threshold: Duration,
+  /// Why the user was last considered idle or active
+  pub reason: Option<Reason>,
+  pub last_success: Option<Instant>,
+  pub last_poll_failed: bool,
+  /// When the user last became idle or active
+  pub last_transition: Option<SystemTime>,
+  /// How long the last successful reading took
+  pub poll_latency: Option<Duration>,
+}
+
+/// Connect to the logind session to sync, and pick its threshold if one is
+/// configured.
+async fn connect_logind(
+  config: &Config,
+) -> Result<Login1SessionProxy<'static>> {
+  let zbus_conn = ZbusConnection::system()
+    .await
+    .context("Failed to connect to system D-Bus")
+    .context(Fatal::DbusUnavailable)?;
+  let x_display = config.source.x_display();
+  let session_path =
+    logind::resolve_session(&zbus_conn, x_display.as_deref()).await;
+  let session_proxy = Login1SessionProxy::builder(&zbus_conn)
+    .path(session_path)?
+    // Always read the current IdleHint from logind when re-asserting
+    .cache_properties(CacheProperties::No)
+    .build()
+    .await
+    .context("Failed to create Login1Session proxy")?;
+  Ok(session_proxy)
+}
+
+/// Set up the idle source, retrying while it can't be reached.
+async fn connect_source(
+  config: &Config,
+  period: Duration,
+) -> Result<IdleSource> {
+  backoff::retry(config.retry, "Connecting to the idle source", || {
+    IdleSource::new(&config.source, period, config.retry)
+  })
+  .await
+}
+
+/// Use the thresholds configured for the synced session, looked up by its
+/// session id and then by its seat name.
+async fn select_session_thresholds(
+  session: &Login1SessionProxy<'_>,
+  config: &mut Config,
+) -> Result<()> {
+  if config.session_thresholds.is_empty() {
+    return Ok(());
+  }
+  let names = async {
+    let id = session.id().await.context("Failed to get session id")?;
+    let (seat, _) = session.seat().await.context("Failed to get seat")?;
+    anyhow::Ok([id, seat])
+  };
+  let names = match names.await {
+    Ok(names) => names,
+    Err(e) => {
+      warn!("Using default idle threshold: {e:#}");
+      return Ok(());
+    }
+  };
+
+  let selected = session_thresholds(
+    &config.session_thresholds,
+    &names,
+    config.max_reported_idle,
+  )?;
+  match selected {
+    Some((name, thresholds)) => {
+      info!(
+        "Using idle threshold of {} seconds configured for {name}",
+        thresholds.enter.as_secs()
+      );
+      config.thresholds = thresholds;
+    }
+    None => {
+      let [id, _] = &names;
+      info!("No idle threshold configured for session {id}, using default");
+    }
+  }
+  Ok(())
+}
+
+/// The thresholds configured for the first of `names` that has any. Only
+/// these are checked against `max_reported_idle`, as entries for other
+/// sessions don't matter here.
+fn session_thresholds<'a>(
+  sessions: &BTreeMap<String, Thresholds>,
+  names: &'a [String],
+  max_reported_idle: Option<Duration>,
+) -> Result<Option<(&'a str, Thresholds)>> {
+  let Some((name, thresholds)) = names.iter().find_map(|name| {
+    let thresholds = sessions.get(name)?;
+    Some((name.as_str(), *thresholds))
+  }) else {
+    return Ok(None);
+  };
+  if max_reported_idle.is_some_and(|max| max < thresholds.enter) {
+    return Err(
+      anyhow!(
+        "--max-reported-idle must not be less than the idle threshold of \
+         {name}"
+      )
+      .context(Fatal::Config),
+    );
+  }
+  Ok(Some((name, thresholds)))
+}
+
+/// How often the idle time is polled.
+pub fn check_interval(config: &Config) -> Duration {
+  if let Some(interval) = config.fixed_interval {
+    return interval;
+  }
+  match config.mode {
+    Mode::Sync => {
+      // The adaptive threshold may go as low as the bottom of its band
+      let enter = match config.adaptive_threshold {
+        Some(band) => config.thresholds.enter.min(band.min()),
+        None => config.thresholds.enter,
+      };
+      (enter / 10).max(MIN_CHECK_INTERVAL)
+    }
+    Mode::ClearOnActivity => MIN_CHECK_INTERVAL,
+  }
+}
+
+pub struct IdleMonitor {
+  source: IdleSource,
+  config: Config,
+  check_interval: Duration,
+  /// `None` while logind is unreachable with `--allow-no-dbus`
+  session_proxy: Option<Login1SessionProxy<'static>>,
+  /// The hint to send once logind is reachable again
+  pending_hint: Option<bool>,
+  /// Whether logind refused the hint, with `--ignore-permission-errors`
+  permission_denied: bool,
+  /// The way of setting the hint that last worked
+  hint_method: Option<HintMethod>,
+  status: watch::Sender<Status>,
+  idle: bool,
+  idle_since: Option<SystemTime>,
+  quiet: bool,
+  screensaver_signals: Option<ActiveChangedStream<'static>>,
+  screensaver_active: bool,
+  /// Activity reported by auxiliary tasks
+  activity: Activity,
+  continuity: ContinuityCheck,
+  smoother: Smoother,
+  /// Whether the last reading was above `max_reported_idle`
+  clamped: bool,
+  last_poll: Instant,
+  adaptive: Option<AdaptiveThreshold>,
+  stats: Stats,
+}
+
+impl IdleMonitor {
+  pub async fn new(mut config: Config) -> Result<Self> {
+    // X doesn't depend on the session being registered, so connect to it
+    // while waiting
+    let early_source = match config.dbus_connect_delay {
+      Some(delay) => {
+        let period = check_interval(&config);
+        let source = connect_source(&config, period).await?;
+        info!("Waiting {}s before connecting to D-Bus", delay.as_secs());
+        sleep(delay).await;
+        Some((period, source))
+      }
+      None => None,
+    };
+
+    // Without --allow-no-dbus, the bus may still be starting up. Otherwise
+    // the main loop keeps trying anyway.
+    let proxy = if config.allow_no_dbus {
+      connect_logind(&config).await
+    } else {
+      backoff::retry(config.retry, "Connecting to logind", || {
+        connect_logind(&config)
+      })
+      .await
+    };
+    let session_proxy = match proxy {
+      Ok(proxy) => {
+        select_session_thresholds(&proxy, &mut config).await?;
+        Some(proxy)
+      }
+      Err(e) if config.allow_no_dbus => {
+        warn!("Continuing without logind, will keep trying: {e:#}");
+        None
+      }
+      Err(e) => return Err(e),
+    };
+
+    let screensaver_signals = if config.follow_screensaver {
+      Some(
+        backoff::retry(
+          config.retry,
+          "Subscribing to the screensaver",
+          screensaver::subscribe,
+        )
+        .await?,
+      )
+    } else {
+      None
+    };
+
+    let networks: Vec<_> = config
+      .watch_networks
+      .iter()
+      .map(|spec| NetworkWatch::new(spec.clone()))
+      .collect::<Result<_>>()?;
+
+    let check_interval = check_interval(&config);
+    let adaptive = config.adaptive_threshold.map(|band| {
+      let adaptive = AdaptiveThreshold::new(band, config.thresholds);
+      config.thresholds = adaptive.thresholds();
+      adaptive
+    });
+    let activity = Activity::default();
+    if !networks.is_empty() {
+      network::spawn(networks, check_interval, activity.clone());
+    }
+    let source = match early_source {
+      // Session thresholds may have changed the poll interval
+      Some((period, source)) if period == check_interval => source,
+      _ => connect_source(&config, check_interval).await?,
+    };
+
+    Ok(Self {
+      source,
+      smoother: Smoother::new(config.smooth_window),
+      clamped: false,
+      stats: Stats::new(config.source.spec.to_string()),
+      config,
+      check_interval,
+      session_proxy,
+      pending_hint: None,
+      permission_denied: false,
+      hint_method: None,
+      status: watch::Sender::new(Status::default()),
+      idle: false,
+      idle_since: None,
+      quiet: false,
+      screensaver_signals,
+      screensaver_active: false,
+      activity,
+      continuity: ContinuityCheck::default(),
+      last_poll: Instant::now(),
+      adaptive,
+    })
+  }
+
+  pub fn thresholds(&self) -> Thresholds {
+    self.config.thresholds
+  }
+
+  /// The system bus connection used to talk to logind, if connected.
+  pub fn dbus_connection(&self) -> Option<&ZbusConnection> {
+    let proxy = self.session_proxy.as_ref()?;
+    Some(proxy.inner().connection())
+  }
+
+  #[cfg(feature = "health")]
+  pub fn check_interval(&self) -> Duration {
+    self.check_interval
+  }
+
+  pub fn subscribe(&self) -> watch::Receiver<Status> {
+    self.status.subscribe()
+  }
+
+  /// Take an idle reading, recording the outcome in the status. Returns
+  /// `None` if this poll should be skipped.
+  async fn read_idle(&mut self) -> Result<Option<Duration>> {
+    let started = Instant::now();
+    let mut result = self.source.idle_time().await;
+    let latency = started.elapsed();
+    if let (Ok(Some(idle)), Some(max)) =
+      (&mut result, self.config.max_reported_idle)
+    {
+      let clamped = *idle > max;
+      if clamped && !self.clamped {
+        warn!(
+          "Idle time of {}s exceeds --max-reported-idle, clamping to {}s",
+          idle.as_secs(),
+          max.as_secs()
+        );
+      } else if !clamped && self.clamped {
+        debug!("Idle time back below --max-reported-idle");
+      }
+      self.clamped = clamped;
+      *idle = (*idle).min(max);
+    }
+    if let (Ok(Some(idle)), Some(last)) = (&mut result, self.activity.last()) {
+      *idle = (*idle).min(last.elapsed());
+    }
+    if !matches!(result, Ok(Some(_))) && !self.source.ended() {
+      self.stats.error(self.config.source.spec.to_string());
+    }
+    self.status.send_modify(|status| match &result {
+      Ok(Some(idle)) => {
+        status.idle_time = *idle;
+        status.last_success = Some(Instant::now());
+        status.last_poll_failed = false;
+        status.poll_latency = Some(latency);
+      }
+      Ok(None) | Err(_) => status.last_poll_failed = true,
+    });
+    result
+  }
+
+  /// Monitor until a signal or the max runtime ends it. Idle source
+  /// settings received on `reloads` replace the current idle source, and
+  /// `refreshes` ask for a poll outside of the regular cadence.
+  pub async fn run(
+    &mut self,
+    mut signals: ExitSignals,
+    mut reloads: mpsc::Receiver<SourceSettings>,
+    mut refreshes: mpsc::Receiver<()>,
+  ) -> Result<()> {
+    let max_runtime = self.config.max_runtime;
+    let deadline = sleep(max_runtime.unwrap_or_default());
+    tokio::pin!(deadline);
+
+    // Timers persist across loop iterations so that other branches firing
+    // don't push back the next poll
+    let mut poll_timer = poll_timer(&self.config, self.check_interval);
+    let reassert_interval = self.config.reassert_interval;
+    let mut reassert_timer =
+      delayed_interval(reassert_interval.unwrap_or(self.check_interval));
+    let mut screensaver = self.screensaver_signals.take();
+    let activity = self.activity.clone();
+    let mut reconnect_timer = delayed_interval(self.check_interval);
+
+    // Push the actual state right away rather than after the first period,
+    // so that restarting while the user is idle doesn't leave a stale hint
+    self.poll().await?;
+
+    loop {
+      if self.source.ended() {
+        info!("Idle source ended, exiting idle monitoring loop...");
+        break;
+      }
+
+      tokio::select! {
+        // After the process was paused, every timer is due at once. Polling
+        // first makes sure nothing acts on the state from before the pause.
+        biased;
+
+        _ = signals.recv() => {
+          info!("Received shutdown signal, exiting idle monitoring loop...");
+          break;
+        }
+
+        _ = &mut deadline, if max_runtime.is_some() => {
+          info!("Max runtime reached, exiting");
+          break;
+        }
+
+        _ = poll_timer.tick() => {
+          self.poll().await?;
+        }
+
+        _ = reassert_timer.tick(), if reassert_interval.is_some() => {
+          self.reassert().await?;
+        }
+
+        _ = activity.notified() => {
+          self.on_activity().await?;
+        }
+
+        Some(settings) = reloads.recv() => {
+          self.reload_source(settings).await;
+        }
+
+        Some(()) = refreshes.recv() => {
+          info!("Refresh requested, polling right away");
+          self.poll().await?;
+        }
+
+        _ = reconnect_timer.tick(), if self.session_proxy.is_none() => {
+          self.reconnect().await?;
+        }
+
+        signal = next_signal(&mut screensaver), if screensaver.is_some() => {
+          match signal {
+            Some(active) => self.follow_screensaver(active).await?,
+            None => {
+              warn!("Screensaver signal stream ended, relying on polling");
+              screensaver = None;
+            }
+          }
+        }
+      }
+    }
+
+    Ok(())
+  }
+
+  async fn poll(&mut self) -> Result<()> {
+    let Some(idle) = self.read_idle().await? else {
+      return Ok(());
+    };
+    let elapsed = self.last_poll.elapsed();
+    self.last_poll = Instant::now();
+    if elapsed > self.check_interval * 2 {
+      debug!("Resuming after {}s without a poll", elapsed.as_secs());
+    }
+
+    match self.config.mode {
+      Mode::Sync => {
+        let raw = idle;
+        let idle = if self.config.require_continuous_idle {
+          self.continuity.update(idle, Instant::now())
+        } else {
+          idle
+        };
+        let idle = self.smoother.update(idle);
+        self.update_quiet_hours();
+        let (state, reason) = self.decide(self.idle, idle);
+        if self.config.explain {
+          println!("{}", self.explain(raw, idle, state, reason));
+        }
+        self.apply(state, reason, idle, "").await?;
+        if self.config.cross_check {
+          self.cross_check(idle).await?;
+        }
+      }
+      Mode::ClearOnActivity => {
+        // Without input the counter grows at least as fast as time passes,
+        // so a reading below the time since the last poll means the counter
+        // was reset in between.
+        if idle < elapsed {
+          self.clear_hint(idle, Reason::ActivityDetected).await?;
+        }
+      }
+    }
+
+    #[cfg(feature = "metrics")]
+    if let Some(statsd) = &self.config.statsd {
+      statsd.poll(self.idle, idle);
+    }
+    Ok(())
+  }
+
+  /// Switch to the idle source described by `settings`, keeping the current
+  /// one if the new one fails to set up.
+  async fn reload_source(&mut self, settings: SourceSettings) {
+    if settings == self.config.source {
+      info!("Idle source unchanged");
+      return;
+    }
+
+    let source =
+      IdleSource::new(&settings, self.check_interval, self.config.retry).await;
+    match source {
+      Ok(source) => {
+        info!("Switched idle source to {}", settings.spec);
+        self.source = source;
+        // Readings of the old source say nothing about the new one
+        self.forget_readings();
+        self.stats.add_source(settings.spec.to_string());
+        self.config.source = settings;
+      }
+      Err(e) => error!(
+        "Failed to set up idle source {}, keeping the current one: {e:#}",
+        settings.spec
+      ),
+    }
+  }
+
+  /// Act on activity reported by an auxiliary task without waiting for the
+  /// next poll.
+  async fn on_activity(&mut self) -> Result<()> {
+    match self.config.mode {
+      Mode::Sync if self.screensaver_active => Ok(()),
+      Mode::Sync => {
+        // Readings from before the activity would take the next poll right
+        // back to idle
+        self.forget_readings();
+        if !self.idle {
+          return Ok(());
+        }
+        self.update_quiet_hours();
+        self
+          .apply(false, Reason::ActivityReported, Duration::ZERO, "")
+          .await
+      }
+      Mode::ClearOnActivity => {
+        self
+          .clear_hint(Duration::ZERO, Reason::ActivityReported)
+          .await
+      }
+    }
+  }
+
+  /// Force the idle hint to false in clear-on-activity mode. Activity shows
+  /// up on most polls while the user is busy, so only clearing a hint that
+  /// was set counts as a transition.
+  async fn clear_hint(&mut self, idle: Duration, reason: Reason) -> Result<()> {
+    // Another component sets the hint, so only logind knows its state
+    let was_idle = match &self.session_proxy {
+      Some(proxy) => proxy.idle_hint().await.unwrap_or_else(|e| {
+        debug!("Failed to get idle hint, assuming it was set: {e:#}");
+        true
+      }),
+      None => self.pending_hint != Some(false),
+    };
+    self
+      .push_hint(false)
+      .await
+      .context("Failed to clear idle hint")?;
+    if was_idle {
+      info!("Idle hint cleared (reason: {reason})");
+      self.record(false, idle, reason);
+    } else {
+      debug!("Idle hint already clear (reason: {reason})");
+    }
+    Ok(())
+  }
+
+  /// Start smoothing and continuity checks over with the next reading.
+  fn forget_readings(&mut self) {
+    self.smoother.clear();
+    self.continuity = ContinuityCheck::default();
+  }
+
+  /// Push an immediate state change announced by the screensaver.
+  async fn follow_screensaver(&mut self, active: bool) -> Result<()> {
+    self.screensaver_active = active;
+    self.update_quiet_hours();
+    let (state, reason) = match self.quiet {
+      true => (false, Reason::QuietHours),
+      false if active => (true, Reason::ScreensaverActive),
+      false => (false, Reason::ScreensaverInactive),
+    };
+    self
+      .apply(state, reason, Duration::ZERO, ", from screensaver signal")
+      .await
+  }
+
+  /// Take on the idle state `state` given the idle time `idle`, pushing it
+  /// to logind and logging transitions with `trigger` appended.
+  async fn apply(
+    &mut self,
+    state: bool,
+    reason: Reason,
+    idle: Duration,
+    trigger: &str,
+  ) -> Result<()> {
+    let was_idle = self.idle;
+    self.idle = state;
+    if !self.config.set_idle_only || (self.idle && !was_idle) {
+      self
+        .push_hint(self.idle)
+        .await
+        .context("Failed to set idle hint")?;
+    }
+
+    if self.idle != was_idle {
+      self.stats.transition(self.idle);
+      if self.config.source.x11.dpms {
+        self.source.force_dpms(!self.idle);
+      }
+      if self.idle {
+        let since = last_input_time(SystemTime::now(), idle);
+        info!(
+          "User is idle (reason: {reason}, inactive since {}{trigger})",
+          DateTime::<Local>::from(since).format("%H:%M:%S")
+        );
+        self.idle_since = Some(since);
+      } else {
+        info!("User is active (reason: {reason}{trigger})");
+        self.idle_since = None;
+      }
+      self.adapt_thresholds();
+      if self.config.set_idle_only && !self.idle {
+        info!("Not resetting idle hint due to --set-idle-only");
+      }
+      self.record(self.idle, idle, reason);
+    }
+
+    let (idle, idle_since) = (self.idle, self.idle_since);
+    if let Some(state_file) = &mut self.config.state_file {
+      state_file.update(idle, idle_since);
+    }
+    let threshold = self.config.thresholds.enter;
+    self.status.send_modify(|status| {
+      if idle != was_idle {
+        status.last_transition = Some(SystemTime::now());
+      }
+      status.idle = idle;
+      status.idle_since = idle_since;
+      status.threshold = threshold;
+      status.reason = Some(reason);
+    });
+    if idle != was_idle {
+      self.run_transition_hook();
+    }
+    Ok(())
+  }
+
+  /// Add a transition to the audit file, the history and the metrics.
+  fn record(&self, idle: bool, idle_time: Duration, reason: Reason) {
+    if let Some(audit) = &self.config.audit {
+      audit.record(idle, idle_time, reason);
+    }
+    if let Some(history) = &self.config.history {
+      history.record(idle, idle_time, reason);
+    }
+    #[cfg(feature = "metrics")]
+    if let Some(statsd) = &self.config.statsd {
+      statsd.transition(idle);
+    }
+  }
+
+  /// Run the `--on-idle` or `--on-active` hook in the background.
+  fn run_transition_hook(&self) {
+    let (name, cmd) = if self.idle {
+      ("idle", &self.config.on_idle)
+    } else {
+      ("active", &self.config.on_active)
+    };
+    let Some(cmd) = cmd.clone() else {
+      return;
+    };
+    let env = self.hook_env();
+    tokio::spawn(async move { hooks::run(name, &cmd, None, &env).await });
+  }
+
+  /// The current state, for the environment of hooks.
+  pub fn hook_env(&self) -> hooks::Env {
+    hooks::Env {
+      status: *self.status.borrow(),
+      backend: self.config.source.spec.to_string(),
+    }
+  }
+
+  /// Collect the inputs of a decision for `--explain`.
+  fn explain(
+    &self,
+    raw: Duration,
+    idle: Duration,
+    state: bool,
+    reason: Reason,
+  ) -> Explanation {
+    let mut overrides = vec![];
+    if self.quiet {
+      overrides.push("quiet-hours");
+    }
+    if self.screensaver_active {
+      overrides.push("screensaver");
+    }
+
+    let thresholds = self.config.thresholds;
+    Explanation {
+      raw,
+      idle,
+      threshold: if self.idle {
+        thresholds.exit
+      } else {
+        thresholds.enter
+      },
+      overrides,
+      idle_state: state,
+      reason,
+    }
+  }
+
+  /// Let `--adaptive-threshold` account for a transition.
+  fn adapt_thresholds(&mut self) {
+    let Some(adaptive) = &mut self.adaptive else {
+      return;
+    };
+    adaptive.transition(self.idle);
+    let thresholds = adaptive.thresholds();
+    if thresholds.enter.as_secs() != self.config.thresholds.enter.as_secs() {
+      info!("Idle threshold adapted to {}s", thresholds.enter.as_secs());
+    }
+    self.config.thresholds = thresholds;
+  }
+
+  /// Whether the user is idle given the idle time, and why.
+  fn decide(&self, was_idle: bool, idle: Duration) -> (bool, Reason) {
+    if self.quiet {
+      (false, Reason::QuietHours)
+    } else if self.screensaver_active {
+      (true, Reason::ScreensaverActive)
+    } else if self.config.thresholds.is_idle(was_idle, idle) {
+      (true, Reason::ThresholdExceeded)
+    } else {
+      (false, Reason::BelowThreshold)
+    }
+  }
+
+  /// Re-evaluate the quiet hours schedule, logging when it kicks in or ends.
+  fn update_quiet_hours(&mut self) {
+    let quiet = schedule::now_in_any(&self.config.quiet_hours);
+    if quiet != self.quiet {
+      if quiet {
+        info!("Quiet hours started, not marking the user idle");
+      } else {
+        info!("Quiet hours ended");
+      }
+      self.quiet = quiet;
+    }
+  }
+
+  /// Read back the idle hint from logind and push our own state again if
+  /// someone else changed it.
+  async fn reassert(&mut self) -> Result<()> {
+    // Nothing to assert until the first reading came in
+    if self.status.borrow().last_success.is_none() {
+      return Ok(());
+    }
+    // The active state is deliberately not pushed in one-way mode
+    if self.config.set_idle_only && !self.idle {
+      return Ok(());
+    }
+
+    let Some(proxy) = &self.session_proxy else {
+      return Ok(());
+    };
+    let hint = match proxy.idle_hint().await {
+      Ok(hint) => hint,
+      Err(e) => {
+        // The next tick tries again
+        warn!("Failed to get idle hint, not re-asserting: {e:#}");
+        return Ok(());
+      }
+    };
+    if hint != self.idle {
+      warn!("Idle hint drifted to {hint}, re-asserting {}", self.idle);
+      self
+        .push_hint(self.idle)
+        .await
+        .context("Failed to set idle hint")?;
+    }
+
+    Ok(())
+  }
+
+  /// Compare logind's view of the session with our own, to spot other idle
+  /// providers. With `--reassert-interval`, our state wins right away.
+  async fn cross_check(&mut self, idle: Duration) -> Result<()> {
+    // The active state is deliberately not pushed in one-way mode
+    if self.config.set_idle_only && !self.idle {
+      return Ok(());
+    }
+
+    let Some(proxy) = &self.session_proxy else {
+      return Ok(());
+    };
+    // A diagnostic that fails is no reason to stop syncing
+    let hint = match proxy.idle_hint().await {
+      Ok(hint) => hint,
+      Err(e) => {
+        warn!("Cross-check: failed to get idle hint: {e:#}");
+        return Ok(());
+      }
+    };
+    if hint != self.idle {
+      let state = |idle| if idle { "idle" } else { "active" };
+      warn!(
+        "Cross-check: logind has the user {} while they are {} here",
+        state(hint),
+        state(self.idle)
+      );
+      if self.config.reassert_interval.is_some() {
+        self
+          .push_hint(self.idle)
+          .await
+          .context("Failed to set idle hint")?;
+      }
+      return Ok(());
+    }
+
+    if hint {
+      let since = match proxy.idle_since_hint().await {
+        Ok(since) => since,
+        Err(e) => {
+          warn!("Cross-check: failed to get idle since hint: {e:#}");
+          return Ok(());
+        }
+      };
+      if since == 0 {
+        // Not known to logind
+        return Ok(());
+      }
+      let since = UNIX_EPOCH + Duration::from_micros(since);
+      let logind_idle = since.elapsed().unwrap_or_default();
+      // We only set the hint once the user has been idle for a while, so
+      // logind can't rightfully have them idle for longer than we do
+      if logind_idle > idle + CROSS_CHECK_TOLERANCE {
+        warn!(
+          "Cross-check: logind has the user idle for {}s, but no input was \
+           seen for only {}s",
+          logind_idle.as_secs(),
+          idle.as_secs()
+        );
+      }
+    }
+
+    Ok(())
+  }
+
+  pub async fn one_shot_check(&mut self) -> Result<()> {
+    let idle = self
+      .read_idle()
+      .await?
+      .context("No idle reading available")?;
+    self.quiet = schedule::now_in_any(&self.config.quiet_hours);
+    let (state, reason) = self.decide(false, idle);
+    debug!(
+      "User is {} (reason: {reason})",
+      if state { "idle" } else { "active" }
+    );
+    if self.config.explain {
+      println!("{}", self.explain(idle, idle, state, reason));
+    }
+    if state || !self.config.set_idle_only {
+      self
+        .push_hint(state)
+        .await
+        .context("Failed to set idle hint")?;
+    }
+    println!(
+      "User is {} (idle for {})",
+      if state { "idle" } else { "active" },
+      format_duration(idle)
+    );
+    Ok(())
+  }
+
+  /// Set logind's idle hint, keeping track of the last one sent. With
+  /// `--allow-no-dbus`, the hint is held back while logind is unreachable.
+  async fn push_hint(&mut self, idle: bool) -> Result<()> {
+    let Some(proxy) = &self.session_proxy else {
+      self.pending_hint = Some(idle);
+      return Ok(());
+    };
+    let method = self.hint_method.unwrap_or(HintMethod::SetIdleHint);
+    match logind::set_idle_hint(proxy, idle, method).await {
+      Ok(method) => {
+        if self.hint_method != Some(method) {
+          info!("Setting the idle hint with {method}");
+          self.hint_method = Some(method);
+        }
+        self.stats.hint(idle);
+        Ok(())
+      }
+      Err(e) if logind::is_access_denied(&e) => {
+        const EXPLANATION: &str = "Not permitted to set idle hint for this \
+                                   session; check that you're running \
+                                   inside the target session";
+        if !self.config.ignore_permission_errors {
+          error!("{EXPLANATION}");
+          return Err(anyhow::Error::new(e).context(Fatal::PermissionDenied));
+        }
+        if !self.permission_denied {
+          warn!("{EXPLANATION}. Monitoring without setting it: {e}");
+          self.permission_denied = true;
+        }
+        Ok(())
+      }
+      Err(e) if self.config.allow_no_dbus => {
+        warn!("Lost logind, will keep trying to reconnect: {e}");
+        self.session_proxy = None;
+        self.pending_hint = Some(idle);
+        Ok(())
+      }
+      Err(e) => Err(e.into()),
+    }
+  }
+
+  /// Try to reach logind again, sending the hint held back meanwhile.
+  async fn reconnect(&mut self) -> Result<()> {
+    let proxy = match connect_logind(&self.config).await {
+      Ok(proxy) => proxy,
+      Err(e) => {
+        debug!("Still no logind: {e:#}");
+        return Ok(());
+      }
+    };
+    select_session_thresholds(&proxy, &mut self.config).await?;
+    info!("Connected to logind");
+    self.session_proxy = Some(proxy);
+    if let Some(idle) = self.pending_hint.take() {
+      self
+        .push_hint(idle)
+        .await
+        .context("Failed to set idle hint")?;
+    }
+    Ok(())
+  }
+
+  /// What happened during the run so far.
+  pub fn summary(&mut self) -> Summary {
+    self.stats.summary()
+  }
+
+  /// Reset the idle hint, returning whether logind took it.
+  pub async fn set_idle_hint_false(&mut self) -> Result<bool> {
+    self
+      .push_hint(false)
+      .await
+      .context("Failed to set idle hint to false")?;
+    Ok(self.session_proxy.is_some() && !self.permission_denied)
+  }
+}
+
+/// A `--print-idle --json` line.
+#[derive(Serialize)]
+struct IdleLine {
+  idle_ms: u128,
+  /// Raw screensaver state by display, with `--query-info`
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  displays: Vec<DisplayInfo>,
+}
+
+#[derive(Serialize)]
+struct DisplayInfo {
+  display: String,
+  #[serde(flatten)]
+  info: ScreensaverInfo,
+}
+
+/// Print the raw idle time at every poll until a signal arrives, without
+/// touching D-Bus.
+pub async fn print_idle(
+  config: &Config,
+  mut signals: ExitSignals,
+  json: bool,
+  query_info: bool,
+) -> Result<()> {
+  let check_interval = check_interval(config);
+  let mut source =
+    IdleSource::new(&config.source, check_interval, config.retry).await?;
+
+  // The display tasks own their connections, so query on connections of
+  // our own
+  let mut raw_sources = vec![];
+  if query_info {
+    let displays: Vec<_> = if config.source.displays.is_empty() {
+      vec![None]
+    } else {
+      config.source.displays.iter().map(Some).collect()
+    };
+    for display in displays {
+      let x_options = XOptions {
+        connect_timeout: config.source.x11.connect_timeout,
+        reply_timeout: config.source.x11.reply_timeout,
+        ..Default::default()
+      };
+      let raw_source =
+        ScreensaverSource::connect(display.map(String::as_str), x_options)
+          .await
+          .context(Fatal::XUnavailable)?;
+      raw_sources.push(raw_source);
+    }
+  }
+
+  let mut ticker = interval(check_interval);
+  ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+  loop {
+    tokio::select! {
+      _ = signals.recv() => return Ok(()),
+      _ = ticker.tick() => {}
+    }
+
+    let Some(idle) = source.idle_time().await? else {
+      if source.ended() {
+        return Ok(());
+      }
+      continue;
+    };
+    let idle_ms = idle.as_millis();
+    if json {
+      let mut displays = vec![];
+      for raw_source in &raw_sources {
+        displays.push(DisplayInfo {
+          display: raw_source.display().unwrap_or_default().to_string(),
+          info: raw_source.query_info().await?,
+        });
+      }
+      let line = IdleLine { idle_ms, displays };
+      println!("{}", serde_json::to_string(&line)?);
+    } else {
+      println!("{idle_ms}");
+    }
+  }
+}
+
+/// The next screensaver state announced on `stream`, or `None` once it
+/// ended.
+async fn next_signal(
+  stream: &mut Option<ActiveChangedStream<'static>>,
+) -> Option<bool> {
+  let stream = stream.as_mut()?;
+  loop {
+    let signal = stream.next().await?;
+    match signal.args() {
+      Ok(args) => return Some(args.active),
+      Err(e) => warn!("Ignoring malformed ActiveChanged signal: {e}"),
+    }
+  }
+}
+
+/// The timer for polls every `period` after the first, aligned to the wall
+/// clock with `--align-polls`.
+pub fn poll_timer(config: &Config, period: Duration) -> Interval {
+  if config.align_polls {
+    aligned_interval(period)
+  } else {
+    delayed_interval(period)
+  }
+}
+
+/// An interval whose first tick is one period from now.
+fn delayed_interval(period: Duration) -> Interval {
+  let mut interval = interval_at(tokio::time::Instant::now() + period, period);
+  interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+  interval
+}
+
+/// An interval ticking whenever the time since the Unix epoch is a multiple
+/// of `period`. Ticks missed during a pause are skipped to stay on the grid.
+fn aligned_interval(period: Duration) -> Interval {
+  let since_epoch = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .unwrap_or_default();
+  let offset = since_epoch.as_nanos() % period.as_nanos();
+  let first = period - Duration::from_nanos(offset as u64);
+  let mut interval = interval_at(tokio::time::Instant::now() + first, period);
+  interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+  interval
+}
+
+/// When the last input happened, given an idle time read at `now`. The
+/// reading tells exactly when that was, while the poll may come up to a
+/// whole interval later.
+fn last_input_time(now: SystemTime, idle: Duration) -> SystemTime {
+  now - idle
+}
+
+/// Format a duration as e.g. "1h 2m 3s", omitting leading zero units.
+pub fn format_duration(duration: Duration) -> String {
+  let secs = duration.as_secs();
+  let (hours, mins, secs) = (secs / 3600, secs / 60 % 60, secs % 60);
+  if hours > 0 {
+    format!("{hours}h {mins}m {secs}s")
+  } else if mins > 0 {
+    format!("{mins}m {secs}s")
+  } else {
+    format!("{secs}s")
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn secs(secs: u64) -> Duration {
+    Duration::from_secs(secs)
+  }
+
+  #[test]
+  fn session_threshold_survives_global_band() {
+    let names = ["c1".to_string(), "seat1".to_string()];
+    let sessions = BTreeMap::from([(
+      "seat1".to_string(),
+      Thresholds::without_band(secs(120)),
+    )]);
+    let selected = session_thresholds(&sessions, &names, Some(secs(900)));
+    let (name, thresholds) = selected.unwrap().unwrap();
+    assert_eq!(name, "seat1");
+    assert_eq!(thresholds, Thresholds::without_band(secs(120)));
+
+    let selected = session_thresholds(&sessions, &names, Some(secs(60)));
+    assert!(selected.is_err());
+    // Entries of other sessions aren't checked
+    let names = ["c2".to_string(), "seat2".to_string()];
+    let selected = session_thresholds(&sessions, &names, Some(secs(60)));
+    assert!(selected.unwrap().is_none());
+  }
+
+  #[test]
+  fn continuity_distrusts_first_reading() {
+    let start = Instant::now();
+    let mut check = ContinuityCheck::default();
+    assert_eq!(check.update(secs(400), start), Duration::ZERO);
+    // The counter dropped right after, so the first reading was bogus
+    assert_eq!(check.update(secs(1), start + secs(1)), secs(1));
+  }
+
+  #[test]
+  fn continuity_covers_threshold_by_rising() {
+    let start = Instant::now();
+    let mut check = ContinuityCheck::default();
+    assert_eq!(check.update(secs(100), start), Duration::ZERO);
+    assert_eq!(check.update(secs(110), start + secs(10)), secs(10));
+    assert_eq!(check.update(secs(120), start + secs(20)), secs(20));
+  }
+
+  #[test]
+  fn continuity_restarts_after_jump() {
+    let start = Instant::now();
+    let mut check = ContinuityCheck::default();
+    check.update(secs(10), start);
+    assert_eq!(check.update(secs(20), start + secs(10)), secs(10));
+    assert_eq!(check.update(secs(500), start + secs(20)), Duration::ZERO);
+    assert_eq!(check.update(secs(510), start + secs(30)), secs(10));
+  }
+
+  #[test]
+  fn continuity_follows_activity() {
+    let start = Instant::now();
+    let mut check = ContinuityCheck::default();
+    check.update(secs(10), start);
+    assert_eq!(check.update(secs(20), start + secs(10)), secs(10));
+    assert_eq!(check.update(secs(2), start + secs(20)), secs(2));
+  }
+
+  #[test]
+  fn smoothing_suppresses_spike() {
+    let mut smoother = Smoother::new(3);
+    let smoothed: Vec<_> = [10, 11, 900, 13, 14]
+      .map(|idle| smoother.update(secs(idle)).as_secs())
+      .into();
+    assert_eq!(smoothed, [10, 11, 11, 13, 14]);
+  }
+
+  #[test]
+  fn smoothing_fills_window() {
+    let mut smoother = Smoother::new(5);
+    // Until the window is full, the median is over the readings so far
+    assert_eq!(smoother.update(secs(8)), secs(8));
+    assert_eq!(smoother.update(secs(2)), secs(8));
+    assert_eq!(smoother.update(secs(4)), secs(4));
+    assert_eq!(smoother.update(secs(6)), secs(6));
+    assert_eq!(smoother.update(secs(1)), secs(4));
+    // From then on, the oldest reading drops out
+    assert_eq!(smoother.update(secs(9)), secs(4));
+    assert_eq!(smoother.update(secs(7)), secs(6));
+  }
+
+  #[test]
+  fn smoothing_forgets_readings_before_activity() {
+    let thresholds = Thresholds::without_band(secs(600));
+    let [mut kept, mut cleared] = [Smoother::new(3), Smoother::new(3)];
+    for smoother in [&mut kept, &mut cleared] {
+      smoother.update(secs(700));
+      smoother.update(secs(710));
+    }
+    // As on reported activity
+    cleared.clear();
+    // The old readings would take the user straight back to idle
+    assert!(thresholds.is_idle(false, kept.update(secs(2))));
+    assert!(!thresholds.is_idle(false, cleared.update(secs(2))));
+  }
+
+  #[test]
+  fn smoothing_window_of_one_keeps_readings() {
+    let mut smoother = Smoother::new(1);
+    for idle in [5, 500, 1, 60] {
+      assert_eq!(smoother.update(secs(idle)), secs(idle));
+    }
+  }
+
+  #[test]
+  fn smoothing_reduces_flapping() {
+    let thresholds = Thresholds {
+      enter: secs(60),
+      exit: secs(60),
+    };
+    // Jitter around the threshold while idle, then activity
+    let noisy = [57, 58, 61, 59, 62, 61, 59, 63, 64, 62, 1, 1, 1];
+    let transitions = |window| {
+      let mut smoother = Smoother::new(window);
+      let mut idle = false;
+      let mut transitions = 0;
+      for reading in noisy {
+        let next = thresholds.is_idle(idle, smoother.update(secs(reading)));
+        transitions += usize::from(next != idle);
+        idle = next;
+      }
+      transitions
+    };
+    assert_eq!(transitions(1), 6);
+    assert_eq!(transitions(3), 2);
+  }
+
+  #[test]
+  fn idle_start_from_reading() {
+    let now = UNIX_EPOCH + secs(1_726_340_892);
+    // Whenever the poll came, the reading pins down the last input
+    let since = last_input_time(now, secs(340));
+    assert_eq!(since, UNIX_EPOCH + secs(1_726_340_552));
+    assert_eq!(now.duration_since(since).unwrap(), secs(340));
+  }
+
+  #[test]
+  fn hysteresis_enters_at_threshold() {
+    let thresholds = Thresholds {
+      enter: secs(300),
+      exit: secs(5),
+    };
+    assert!(!thresholds.is_idle(false, secs(299)));
+    assert!(thresholds.is_idle(false, secs(300)));
+    assert!(thresholds.is_idle(false, secs(301)));
+  }
+
+  #[test]
+  fn hysteresis_stays_idle_inside_band() {
+    let thresholds = Thresholds {
+      enter: secs(300),
+      exit: secs(5),
+    };
+    // Below the enter threshold, but still above the exit threshold
+    assert!(thresholds.is_idle(true, secs(299)));
+    assert!(thresholds.is_idle(true, secs(6)));
+    // Active users inside the band stay active
+    assert!(!thresholds.is_idle(false, secs(6)));
+    assert!(!thresholds.is_idle(false, secs(299)));
+  }
+
+  #[test]
+  fn hysteresis_leaves_at_exit_threshold() {
+    let thresholds = Thresholds {
+      enter: secs(300),
+      exit: secs(5),
+    };
+    assert!(thresholds.is_idle(true, secs(5)));
+    assert!(!thresholds.is_idle(true, Duration::from_millis(4999)));
+    assert!(!thresholds.is_idle(true, Duration::ZERO));
+  }
+
+  #[test]
+  fn hysteresis_without_band() {
+    let thresholds = Thresholds {
+      enter: secs(60),
+      exit: secs(60),
+    };
+    for was_idle in [false, true] {
+      assert!(!thresholds.is_idle(was_idle, Duration::from_millis(59999)));
+      assert!(thresholds.is_idle(was_idle, secs(60)));
+    }
+  }
+}
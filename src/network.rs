@@ -0,0 +1,181 @@
+use anyhow::{bail, Context, Result};
+use std::{fmt, fs, str::FromStr, time::Duration};
+use tokio::time::{interval_at, Instant, MissedTickBehavior};
+use tracing::{debug, warn};
+
+use crate::activity::Activity;
+
+/// A network interface whose traffic counts as activity once it reaches
+/// `min_bytes` between two polls, e.g. `eth0:4096`.
+#[derive(Clone, Debug)]
+pub struct NetworkSpec {
+  iface: String,
+  min_bytes: u64,
+}
+
+impl FromStr for NetworkSpec {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let (iface, min_bytes) = s
+      .rsplit_once(':')
+      .filter(|(iface, _)| !iface.is_empty())
+      .ok_or_else(|| {
+        format!("invalid network watch '{s}', expected <iface>:<min-bytes>")
+      })?;
+    let min_bytes = min_bytes
+      .parse()
+      .map_err(|e| format!("invalid byte count '{min_bytes}': {e}"))?;
+
+    Ok(Self {
+      iface: iface.to_string(),
+      min_bytes,
+    })
+  }
+}
+
+impl fmt::Display for NetworkSpec {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}:{}", self.iface, self.min_bytes)
+  }
+}
+
+/// Samples the traffic on an interface.
+pub struct NetworkWatch {
+  spec: NetworkSpec,
+  last_bytes: u64,
+}
+
+impl NetworkWatch {
+  pub fn new(spec: NetworkSpec) -> Result<Self> {
+    let last_bytes = read_bytes(&spec.iface)?;
+    Ok(Self { spec, last_bytes })
+  }
+
+  /// Sample the interface counters, returning whether there was enough
+  /// traffic since the previous sample.
+  fn sample(&mut self) -> Result<bool> {
+    let bytes = read_bytes(&self.spec.iface)?;
+    Ok(self.record(bytes))
+  }
+
+  /// Take `bytes` as the latest counter value, returning whether enough
+  /// traffic passed since the previous one.
+  fn record(&mut self, bytes: u64) -> bool {
+    let active = bytes.wrapping_sub(self.last_bytes) >= self.spec.min_bytes;
+    self.last_bytes = bytes;
+    active
+  }
+}
+
+/// Sample `watches` every `period` in the background, reporting traffic as
+/// user activity.
+pub fn spawn(
+  mut watches: Vec<NetworkWatch>,
+  period: Duration,
+  activity: Activity,
+) {
+  tokio::spawn(async move {
+    let mut ticker = interval_at(Instant::now() + period, period);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+      ticker.tick().await;
+      for watch in &mut watches {
+        match watch.sample() {
+          Ok(true) => {
+            debug!("Traffic on {}, user is active", watch.spec.iface);
+            activity.reset_activity();
+          }
+          Ok(false) => {}
+          Err(e) => warn!("Ignoring traffic on {}: {e:#}", watch.spec.iface),
+        }
+      }
+    }
+  });
+}
+
+/// Total bytes received and transmitted on `iface`, from /proc/net/dev.
+fn read_bytes(iface: &str) -> Result<u64> {
+  let dev = fs::read_to_string("/proc/net/dev")
+    .context("Failed to read /proc/net/dev")?;
+  parse_bytes(&dev, iface)
+}
+
+/// Total bytes of `iface` in the contents of /proc/net/dev.
+fn parse_bytes(dev: &str, iface: &str) -> Result<u64> {
+  let counters = dev
+    .lines()
+    .filter_map(|line| line.split_once(':'))
+    .find(|(name, _)| name.trim() == iface)
+    .map(|(_, counters)| counters)
+    .with_context(|| format!("No network interface named {iface}"))?;
+
+  // Received bytes come first, transmitted bytes are the 9th column
+  let fields: Vec<u64> = counters
+    .split_whitespace()
+    .map(str::parse)
+    .collect::<Result<_, _>>()
+    .with_context(|| format!("Malformed counters for {iface}"))?;
+  match (fields.first(), fields.get(8)) {
+    (Some(rx), Some(tx)) => Ok(rx + tx),
+    _ => bail!("Malformed counters for {iface}"),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const DEV: &str = "\
+Inter-|   Receive                                                |  Transmit
+ face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed
+    lo:    1200      10    0    0    0     0          0         0     1200      10    0    0    0     0       0          0
+  eth0: 5000000    4000    0    0    0     0          0        12   250000    2000    0    0    0     0       0          0
+";
+
+  fn watch(min_bytes: u64, last_bytes: u64) -> NetworkWatch {
+    NetworkWatch {
+      spec: NetworkSpec {
+        iface: "eth0".to_string(),
+        min_bytes,
+      },
+      last_bytes,
+    }
+  }
+
+  #[test]
+  fn parses_specs() {
+    let spec: NetworkSpec = "eth0:4096".parse().unwrap();
+    assert_eq!((spec.iface.as_str(), spec.min_bytes), ("eth0", 4096));
+    assert_eq!(spec.to_string(), "eth0:4096");
+    assert!("eth0".parse::<NetworkSpec>().is_err());
+    assert!(":4096".parse::<NetworkSpec>().is_err());
+    assert!("eth0:4k".parse::<NetworkSpec>().is_err());
+    assert!("eth0:-1".parse::<NetworkSpec>().is_err());
+  }
+
+  #[test]
+  fn sums_received_and_transmitted_bytes() {
+    assert_eq!(parse_bytes(DEV, "eth0").unwrap(), 5_250_000);
+    assert_eq!(parse_bytes(DEV, "lo").unwrap(), 2400);
+    assert!(parse_bytes(DEV, "wlan0").is_err());
+    assert!(parse_bytes("  eth0: 1 2 3\n", "eth0").is_err());
+  }
+
+  #[test]
+  fn counts_traffic_since_previous_sample() {
+    let mut watch = watch(1000, 5000);
+    assert!(!watch.record(5999));
+    // The previous sample moved along even without enough traffic
+    assert!(!watch.record(6998));
+    assert!(watch.record(7998));
+    assert!(!watch.record(7998));
+  }
+
+  #[test]
+  fn survives_counter_wraparound() {
+    let mut watch = watch(1000, u64::MAX - 499);
+    assert!(watch.record(500));
+    assert!(!watch.record(600));
+  }
+}
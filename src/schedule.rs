@@ -0,0 +1,98 @@
+use chrono::{Local, NaiveTime};
+use std::{fmt, str::FromStr};
+
+/// A daily time range in local time, e.g. `18:00-23:30`. Ranges ending
+/// before they start wrap around midnight.
+#[derive(Clone, Copy, Debug)]
+pub struct TimeRange {
+  start: NaiveTime,
+  end: NaiveTime,
+}
+
+impl TimeRange {
+  pub fn contains(&self, time: NaiveTime) -> bool {
+    if self.start < self.end {
+      self.start <= time && time < self.end
+    } else {
+      time >= self.start || time < self.end
+    }
+  }
+}
+
+impl FromStr for TimeRange {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let parse = |time: &str| {
+      NaiveTime::parse_from_str(time.trim(), "%H:%M")
+        .map_err(|e| format!("invalid time '{time}': {e}"))
+    };
+
+    let (start, end) = s.split_once('-').ok_or_else(|| {
+      format!("invalid time range '{s}', expected HH:MM-HH:MM")
+    })?;
+    let (start, end) = (parse(start)?, parse(end)?);
+    if start == end {
+      return Err(format!("time range '{s}' is empty"));
+    }
+
+    Ok(Self { start, end })
+  }
+}
+
+impl fmt::Display for TimeRange {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{}-{}",
+      self.start.format("%H:%M"),
+      self.end.format("%H:%M")
+    )
+  }
+}
+
+/// Whether the current local time falls into any of `ranges`.
+pub fn now_in_any(ranges: &[TimeRange]) -> bool {
+  let now = Local::now().time();
+  ranges.iter().any(|range| range.contains(now))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn at(time: &str) -> NaiveTime {
+    NaiveTime::parse_from_str(time, "%H:%M").unwrap()
+  }
+
+  #[test]
+  fn parses_ranges() {
+    let range: TimeRange = " 18:00 - 23:30 ".parse().unwrap();
+    assert_eq!(range.to_string(), "18:00-23:30");
+    assert!("18:00".parse::<TimeRange>().is_err());
+    assert!("18:00-24:00".parse::<TimeRange>().is_err());
+    assert!("6pm-11pm".parse::<TimeRange>().is_err());
+    assert!("07:00-07:00".parse::<TimeRange>().is_err());
+  }
+
+  #[test]
+  fn contains_start_but_not_end() {
+    let range: TimeRange = "18:00-23:30".parse().unwrap();
+    assert!(!range.contains(at("17:59")));
+    assert!(range.contains(at("18:00")));
+    assert!(range.contains(at("23:29")));
+    assert!(!range.contains(at("23:30")));
+    assert!(!range.contains(at("03:00")));
+  }
+
+  #[test]
+  fn wraps_around_midnight() {
+    let range: TimeRange = "22:00-07:00".parse().unwrap();
+    assert!(!range.contains(at("21:59")));
+    assert!(range.contains(at("22:00")));
+    assert!(range.contains(at("00:00")));
+    assert!(range.contains(at("06:59")));
+    assert!(!range.contains(at("07:00")));
+    assert!(!range.contains(at("12:00")));
+  }
+}
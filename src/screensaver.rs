@@ -0,0 +1,26 @@
+use anyhow::{Context, Result};
+use zbus::{proxy, Connection};
+
+#[proxy(
+  interface = "org.freedesktop.ScreenSaver",
+  default_service = "org.freedesktop.ScreenSaver",
+  default_path = "/org/freedesktop/ScreenSaver"
+)]
+pub trait ScreenSaver {
+  #[zbus(signal)]
+  fn active_changed(&self, active: bool) -> zbus::Result<()>;
+}
+
+/// Subscribe to the desktop's screensaver turning on and off.
+pub async fn subscribe() -> Result<ActiveChangedStream<'static>> {
+  let conn = Connection::session()
+    .await
+    .context("Failed to connect to session D-Bus")?;
+  let proxy = ScreenSaverProxy::new(&conn)
+    .await
+    .context("Failed to create ScreenSaver proxy")?;
+  proxy
+    .receive_active_changed()
+    .await
+    .context("Failed to subscribe to ScreenSaver.ActiveChanged")
+}
@@ -0,0 +1,59 @@
+use anyhow::{bail, Context, Result};
+
+use crate::{
+  exit::Fatal,
+  monitor::Config,
+  x11::{ScreensaverSource, XOptions},
+};
+
+/// Press and release a key without any meaning on every display, and check
+/// that the idle counter went back to zero for it. This injects synthetic
+/// input, so it only runs when explicitly asked for.
+pub async fn run(config: &Config) -> Result<()> {
+  let displays: Vec<_> = if config.source.displays.is_empty() {
+    vec![None]
+  } else {
+    config.source.displays.iter().map(Some).collect()
+  };
+  let x_options = XOptions {
+    connect_timeout: config.source.x11.connect_timeout,
+    reply_timeout: config.source.x11.reply_timeout,
+    ..Default::default()
+  };
+
+  let mut failed = 0;
+  for display in displays {
+    let source =
+      ScreensaverSource::connect(display.map(String::as_str), x_options)
+        .await
+        .context(Fatal::XUnavailable)?;
+    let name = source.display().unwrap_or("default display").to_string();
+    let Some(check) = source.simulate_input().await? else {
+      println!(
+        "{name}: X server lacks the XTEST extension, can't simulate input"
+      );
+      failed += 1;
+      continue;
+    };
+
+    let outcome = if check.reset() {
+      "idle counter reset"
+    } else {
+      failed += 1;
+      "idle counter NOT reset"
+    };
+    println!(
+      "{name}: {outcome} by key code {} ({}ms before, {}ms after)",
+      check.keycode,
+      check.before.as_millis(),
+      check.after.as_millis()
+    );
+  }
+
+  if failed > 0 {
+    bail!(
+      "Simulated input did not reset the idle counter on {failed} display(s)"
+    );
+  }
+  Ok(())
+}
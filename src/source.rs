@@ -0,0 +1,433 @@
+use anyhow::{bail, Context, Result};
+use std::{
+  fmt, fs,
+  io::{self, BufRead, ErrorKind},
+  os::unix::fs::FileTypeExt,
+  path::{Path, PathBuf},
+  str::FromStr,
+  thread,
+  time::{Duration, Instant},
+};
+use tokio::{net::unix::pipe, sync::mpsc};
+use tracing::warn;
+
+#[cfg(feature = "libinput")]
+use crate::libinput::LibinputSource;
+#[cfg(feature = "xinput2")]
+use crate::xinput::XInputSource;
+use crate::{
+  backoff::RetryConfig,
+  logind,
+  supervisor::DisplaySupervisor,
+  x11::{ScreensaverSource, XOptions},
+};
+
+/// Where idle readings come from, as given on the command line.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SourceSpec {
+  /// The X11 MIT-SCREEN-SAVER extension
+  Screensaver,
+  /// Idle milliseconds written to a named pipe or a regular file
+  Fifo(PathBuf),
+  /// Idle milliseconds read from stdin, one per poll, for tests
+  Stdin,
+  /// The input devices of the seat, through libinput
+  #[cfg(feature = "libinput")]
+  Libinput,
+  /// XInput2 raw input events
+  #[cfg(feature = "xinput2")]
+  Xinput2,
+}
+
+/// The sources that can be selected, as probed by `--list-sources`.
+const SOURCE_NAMES: &[&str] = &[
+  "screensaver",
+  "fifo:<path>",
+  #[cfg(feature = "libinput")]
+  "libinput",
+  #[cfg(feature = "xinput2")]
+  "xinput2",
+];
+
+impl FromStr for SourceSpec {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.split_once(':') {
+      None if s == "screensaver" => Ok(Self::Screensaver),
+      // Not advertised, it is meant for tests
+      None if s == "stdin" => Ok(Self::Stdin),
+      #[cfg(feature = "libinput")]
+      None if s == "libinput" => Ok(Self::Libinput),
+      #[cfg(feature = "xinput2")]
+      None if s == "xinput2" => Ok(Self::Xinput2),
+      Some(("fifo", path)) if !path.is_empty() => {
+        Ok(Self::Fifo(PathBuf::from(path)))
+      }
+      _ => {
+        let expected: Vec<_> = SOURCE_NAMES
+          .iter()
+          .map(|name| format!("'{name}'"))
+          .collect();
+        Err(format!(
+          "invalid idle source '{s}', expected one of {}",
+          expected.join(", ")
+        ))
+      }
+    }
+  }
+}
+
+impl fmt::Display for SourceSpec {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Screensaver => write!(f, "screensaver"),
+      Self::Fifo(path) => write!(f, "fifo:{}", path.display()),
+      Self::Stdin => write!(f, "stdin"),
+      #[cfg(feature = "libinput")]
+      Self::Libinput => write!(f, "libinput"),
+      #[cfg(feature = "xinput2")]
+      Self::Xinput2 => write!(f, "xinput2"),
+    }
+  }
+}
+
+/// Everything that determines the idle source, which can change on reload.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceSettings {
+  pub spec: SourceSpec,
+  pub displays: Vec<String>,
+  pub x11: XOptions,
+  /// Input devices whose events don't count as activity
+  #[cfg(feature = "libinput")]
+  pub ignore_devices: Vec<String>,
+}
+
+impl SourceSettings {
+  /// The X display whose logind session to sync, if the source reads one.
+  pub fn x_display(&self) -> Option<String> {
+    match self.spec {
+      SourceSpec::Screensaver => logind::monitored_display(&self.displays),
+      #[cfg(feature = "xinput2")]
+      SourceSpec::Xinput2 => logind::monitored_display(&self.displays),
+      SourceSpec::Fifo(_) | SourceSpec::Stdin => None,
+      #[cfg(feature = "libinput")]
+      SourceSpec::Libinput => None,
+    }
+  }
+}
+
+pub enum IdleSource {
+  Screensaver(DisplaySupervisor),
+  Fifo(FifoSource),
+  Stdin(StdinSource),
+  #[cfg(feature = "libinput")]
+  Libinput(LibinputSource),
+  #[cfg(feature = "xinput2")]
+  Xinput2(XInputSource),
+}
+
+impl IdleSource {
+  /// Set up the idle source described by `settings`. Sources that poll in
+  /// the background do so every `period`.
+  pub async fn new(
+    settings: &SourceSettings,
+    period: Duration,
+    retry: RetryConfig,
+  ) -> Result<Self> {
+    let displays: Vec<_> = if settings.displays.is_empty() {
+      vec![None]
+    } else {
+      settings.displays.iter().cloned().map(Some).collect()
+    };
+    Ok(match &settings.spec {
+      SourceSpec::Screensaver => Self::Screensaver(
+        DisplaySupervisor::new(&displays, period, retry, settings.x11).await?,
+      ),
+      SourceSpec::Fifo(path) => Self::Fifo(FifoSource::new(path)?),
+      SourceSpec::Stdin => Self::Stdin(StdinSource::new()?),
+      #[cfg(feature = "libinput")]
+      SourceSpec::Libinput => {
+        Self::Libinput(LibinputSource::new(&settings.ignore_devices)?)
+      }
+      #[cfg(feature = "xinput2")]
+      SourceSpec::Xinput2 => match XInputSource::new(&displays, retry).await {
+        Ok(source) => Self::Xinput2(source),
+        // Without X at all, the screensaver wouldn't work either
+        Err(e) if e.is::<crate::exit::Fatal>() => return Err(e),
+        Err(e) => {
+          warn!("Falling back to the screensaver idle source: {e:#}");
+          Self::Screensaver(
+            DisplaySupervisor::new(&displays, period, retry, settings.x11)
+              .await?,
+          )
+        }
+      },
+    })
+  }
+
+  /// Force the screen on or off with sources that talk to X.
+  pub fn force_dpms(&self, on: bool) {
+    if let Self::Screensaver(source) = self {
+      source.force_dpms(on);
+    }
+  }
+
+  /// Whether the source has run out of readings for good.
+  pub fn ended(&self) -> bool {
+    matches!(self, Self::Stdin(source) if source.ended)
+  }
+
+  /// Read the current idle time. Returns `None` if no usable reading is
+  /// available for this poll.
+  pub async fn idle_time(&mut self) -> Result<Option<Duration>> {
+    match self {
+      Self::Screensaver(source) => source.idle_time().await,
+      Self::Fifo(source) => Ok(source.idle_time()),
+      Self::Stdin(source) => source.idle_time().await,
+      #[cfg(feature = "libinput")]
+      Self::Libinput(source) => Ok(Some(source.idle_time())),
+      #[cfg(feature = "xinput2")]
+      Self::Xinput2(source) => source.idle_time().map(Some),
+    }
+  }
+}
+
+/// Probe every idle source once and print whether it is available along
+/// with its current reading. A failing source doesn't stop the listing.
+pub async fn list_sources(settings: &SourceSettings) {
+  let displays: Vec<_> = if settings.displays.is_empty() {
+    vec![None]
+  } else {
+    settings
+      .displays
+      .iter()
+      .map(|display| Some(display.as_str()))
+      .collect()
+  };
+  for display in displays {
+    let result = match ScreensaverSource::connect(display, settings.x11).await {
+      Ok(source) => source.idle_time().await,
+      Err(e) => Err(e),
+    };
+    let name = display
+      .map(str::to_string)
+      .or_else(|| std::env::var("DISPLAY").ok())
+      .unwrap_or_else(|| "no display".to_string());
+    print_probe(&format!("screensaver ({name})"), result);
+  }
+
+  match &settings.spec {
+    SourceSpec::Fifo(path) => {
+      let result =
+        FifoSource::new(path).and_then(|mut source| match &mut source.pipe {
+          Some(pipe) => pipe.idle_time(),
+          None => read_file(path),
+        });
+      print_probe(&settings.spec.to_string(), result);
+    }
+    _ => {
+      println!("fifo: not probed, select it with --idle-source fifo:<path>")
+    }
+  }
+
+  // A fresh source has seen no events yet, so there is no reading to show
+  #[cfg(feature = "libinput")]
+  match LibinputSource::new(&settings.ignore_devices) {
+    Ok(source) => {
+      println!("libinput: available, {} devices", source.device_count())
+    }
+    Err(e) => println!("libinput: unavailable: {e:#}"),
+  }
+
+  #[cfg(feature = "xinput2")]
+  {
+    let displays: Vec<_> = if settings.displays.is_empty() {
+      vec![None]
+    } else {
+      settings.displays.iter().cloned().map(Some).collect()
+    };
+    // The source is dropped right away, so it never reconnects
+    let retry = RetryConfig {
+      initial_delay: Duration::ZERO,
+      max_delay: Duration::ZERO,
+      multiplier: 1.0,
+      max_attempts: 1,
+    };
+    let result = match XInputSource::new(&displays, retry).await {
+      Ok(source) => source.idle_time(),
+      Err(e) => Err(e),
+    };
+    print_probe("xinput2", result);
+  }
+}
+
+fn print_probe(name: &str, result: Result<Duration>) {
+  match result {
+    Ok(idle) => println!("{name}: available, idle {} ms", idle.as_millis()),
+    Err(e) => println!("{name}: unavailable: {e:#}"),
+  }
+}
+
+/// Reads idle milliseconds, one value per line, from an external writer.
+///
+/// A regular file is re-read on every poll and its last line is taken as
+/// the current idle time. A named pipe is drained on every poll and the
+/// most recent line wins; if nothing new was written since, the previous
+/// value is assumed to have kept growing.
+pub struct FifoSource {
+  path: PathBuf,
+  pipe: Option<FifoPipe>,
+}
+
+struct FifoPipe {
+  receiver: pipe::Receiver,
+  buf: String,
+  last: Option<(Duration, Instant)>,
+}
+
+impl FifoSource {
+  fn new(path: &Path) -> Result<Self> {
+    let is_fifo = fs::metadata(path)
+      .map(|meta| meta.file_type().is_fifo())
+      .unwrap_or(false);
+
+    let pipe = if is_fifo {
+      // Opening read-write keeps the pipe from reporting EOF while no
+      // writer has it open
+      let receiver = pipe::OpenOptions::new()
+        .read_write(true)
+        .open_receiver(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+      Some(FifoPipe {
+        receiver,
+        buf: String::new(),
+        last: None,
+      })
+    } else {
+      None
+    };
+
+    Ok(Self {
+      path: path.to_path_buf(),
+      pipe,
+    })
+  }
+
+  fn idle_time(&mut self) -> Option<Duration> {
+    let result = match &mut self.pipe {
+      Some(pipe) => pipe.idle_time(),
+      None => read_file(&self.path),
+    };
+
+    match result {
+      Ok(idle) => Some(idle),
+      Err(e) => {
+        warn!(
+          "Skipping poll, no idle reading from {}: {e:#}",
+          self.path.display()
+        );
+        None
+      }
+    }
+  }
+}
+
+impl FifoPipe {
+  fn idle_time(&mut self) -> Result<Duration> {
+    let mut chunk = [0; 4096];
+    loop {
+      match self.receiver.try_read(&mut chunk) {
+        Ok(0) => break,
+        Ok(n) => self.buf.push_str(&String::from_utf8_lossy(&chunk[..n])),
+        Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+        Err(e) => return Err(e).context("Failed to read from pipe"),
+      }
+    }
+
+    // Only complete lines count, keep a partially written one for later
+    if let Some(end) = self.buf.rfind('\n') {
+      let lines: String = self.buf.drain(..=end).collect();
+      let idle = parse_last_line(&lines)?;
+      self.last = Some((idle, Instant::now()));
+    }
+
+    match self.last {
+      Some((idle, read_at)) => Ok(idle + read_at.elapsed()),
+      None => bail!("nothing has been written yet"),
+    }
+  }
+}
+
+/// Reads idle milliseconds separated by whitespace from stdin, taking one
+/// value per poll, so that tests can drive the decision logic with fixture
+/// data. Polls wait for the next value, and the source ends at EOF.
+pub struct StdinSource {
+  values: mpsc::Receiver<Result<Duration>>,
+  ended: bool,
+}
+
+impl StdinSource {
+  fn new() -> Result<Self> {
+    let (tx, values) = mpsc::channel(16);
+    // A thread of its own, as a blocking read would hold up shutdown of
+    // the runtime's blocking pool
+    thread::Builder::new()
+      .name("stdin".to_string())
+      .spawn(move || {
+        for line in io::stdin().lock().lines() {
+          let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+              let _ = tx.blocking_send(Err(e).context("Failed to read stdin"));
+              return;
+            }
+          };
+          for value in line.split_whitespace() {
+            let idle = value
+              .parse()
+              .map(Duration::from_millis)
+              .with_context(|| format!("malformed idle value '{value}'"));
+            if tx.blocking_send(idle).is_err() {
+              return;
+            }
+          }
+        }
+      })
+      .context("Failed to spawn stdin thread")?;
+    Ok(Self {
+      values,
+      ended: false,
+    })
+  }
+
+  async fn idle_time(&mut self) -> Result<Option<Duration>> {
+    match self.values.recv().await {
+      Some(idle) => idle.map(Some),
+      None => {
+        self.ended = true;
+        Ok(None)
+      }
+    }
+  }
+}
+
+fn read_file(path: &Path) -> Result<Duration> {
+  let content = fs::read_to_string(path).map_err(|e| match e.kind() {
+    ErrorKind::NotFound => io::Error::new(e.kind(), "file not found"),
+    _ => e,
+  })?;
+  parse_last_line(&content)
+}
+
+fn parse_last_line(content: &str) -> Result<Duration> {
+  let line = content
+    .lines()
+    .map(str::trim)
+    .rfind(|line| !line.is_empty())
+    .context("no value found")?;
+  let idle_ms: u64 = line
+    .parse()
+    .with_context(|| format!("malformed idle value '{line}'"))?;
+  Ok(Duration::from_millis(idle_ms))
+}
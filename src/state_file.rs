@@ -0,0 +1,91 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::{
+  fs,
+  path::{Path, PathBuf},
+  time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::{debug, warn};
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StateFormat {
+  /// `idle` or `active` on a line of its own
+  #[default]
+  Plain,
+  /// A JSON object like `{"state":"idle","idle_since":1726340592}`
+  Json,
+}
+
+#[derive(Serialize)]
+struct Record {
+  state: &'static str,
+  /// Unix time of the last input while idle
+  #[serde(skip_serializing_if = "Option::is_none")]
+  idle_since: Option<u64>,
+}
+
+/// A file holding the current idle state for status bars and the like. It
+/// is replaced atomically, so readers never see a partial write.
+#[derive(Clone, Debug)]
+pub struct StateFile {
+  path: PathBuf,
+  format: StateFormat,
+  /// The state last written, if any
+  written: Option<bool>,
+}
+
+impl StateFile {
+  pub fn new(path: &Path, format: StateFormat) -> Self {
+    Self {
+      path: path.to_path_buf(),
+      format,
+      written: None,
+    }
+  }
+
+  /// Write the state unless it is already in the file. Failures are logged
+  /// rather than returned, like a missed audit record they shouldn't stop
+  /// the daemon.
+  pub fn update(&mut self, idle: bool, idle_since: Option<SystemTime>) {
+    if self.written == Some(idle) {
+      return;
+    }
+    match self.write(idle, idle_since) {
+      Ok(()) => self.written = Some(idle),
+      Err(e) => warn!("Failed to update {}: {e:#}", self.path.display()),
+    }
+  }
+
+  fn write(&self, idle: bool, idle_since: Option<SystemTime>) -> Result<()> {
+    let state = if idle { "idle" } else { "active" };
+    let content = match self.format {
+      StateFormat::Plain => format!("{state}\n"),
+      StateFormat::Json => {
+        let idle_since = idle_since.map(|since| {
+          since
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+        });
+        let json = serde_json::to_string(&Record { state, idle_since })?;
+        format!("{json}\n")
+      }
+    };
+
+    // A rename within the same directory replaces the file atomically
+    let mut temp = self.path.clone().into_os_string();
+    temp.push(".tmp");
+    fs::write(&temp, content).context("Failed to write temporary file")?;
+    fs::rename(&temp, &self.path).context("Failed to replace file")
+  }
+
+  /// Remove the file on a clean exit.
+  pub fn remove(&self) {
+    match fs::remove_file(&self.path) {
+      Ok(()) => debug!("Removed {}", self.path.display()),
+      Err(e) => warn!("Failed to remove {}: {e}", self.path.display()),
+    }
+  }
+}
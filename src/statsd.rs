@@ -0,0 +1,70 @@
+use anyhow::{Context, Result};
+use std::{
+  fmt::Write as _,
+  net::{ToSocketAddrs, UdpSocket},
+  sync::Arc,
+  time::Duration,
+};
+use tracing::debug;
+
+const PREFIX: &str = "x11_idle_sync";
+
+/// Pushes metrics to a statsd collector over UDP. Sending is fire and
+/// forget; a collector that is away only shows in the debug log.
+#[derive(Clone, Debug)]
+pub struct Statsd {
+  socket: Arc<UdpSocket>,
+}
+
+impl Statsd {
+  /// Resolve `addr`, like `localhost:8125`, and set up a socket for it.
+  pub fn connect(addr: &str) -> Result<Self> {
+    let target = addr
+      .to_socket_addrs()
+      .with_context(|| format!("Failed to resolve statsd address {addr}"))?
+      .next()
+      .with_context(|| format!("No address found for {addr}"))?;
+    let local = if target.is_ipv4() {
+      "0.0.0.0:0"
+    } else {
+      "[::]:0"
+    };
+    let socket =
+      UdpSocket::bind(local).context("Failed to bind statsd socket")?;
+    socket
+      .connect(target)
+      .with_context(|| format!("Failed to set statsd address {target}"))?;
+    socket
+      .set_nonblocking(true)
+      .context("Failed to set up statsd socket")?;
+    Ok(Self {
+      socket: Arc::new(socket),
+    })
+  }
+
+  /// Report the outcome of a poll as gauges.
+  pub fn poll(&self, idle: bool, idle_time: Duration) {
+    let mut packet = String::new();
+    let _ = writeln!(packet, "{PREFIX}.idle:{}|g", u8::from(idle));
+    let _ = write!(
+      packet,
+      "{PREFIX}.idle_seconds:{:.3}|g",
+      idle_time.as_secs_f64()
+    );
+    self.send(&packet);
+  }
+
+  /// Count a change of the idle state.
+  pub fn transition(&self, idle: bool) {
+    let state = if idle { "idle" } else { "active" };
+    self.send(&format!(
+      "{PREFIX}.transitions:1|c\n{PREFIX}.transitions.{state}:1|c"
+    ));
+  }
+
+  fn send(&self, packet: &str) {
+    if let Err(e) = self.socket.send(packet.as_bytes()) {
+      debug!("Failed to send metrics to statsd: {e}");
+    }
+  }
+}
@@ -0,0 +1,110 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, SecondsFormat};
+use serde::Serialize;
+use std::{
+  collections::BTreeMap,
+  fs,
+  path::Path,
+  time::{Duration, Instant},
+};
+
+/// Counters accumulated over a run, for `--summary-json`.
+pub struct Stats {
+  started: DateTime<Local>,
+  /// When the current state began, and whether it is idle
+  state: (Instant, bool),
+  active: Duration,
+  idle: Duration,
+  transitions: u64,
+  /// Failed polls by idle source
+  errors: BTreeMap<String, u64>,
+  /// The hint last sent to logind
+  hint: Option<bool>,
+}
+
+#[derive(Serialize)]
+pub struct Summary {
+  start: String,
+  stop: String,
+  active_ms: u64,
+  idle_ms: u64,
+  transitions: u64,
+  errors: BTreeMap<String, u64>,
+  final_hint: Option<bool>,
+}
+
+impl Stats {
+  pub fn new(source: String) -> Self {
+    Self {
+      started: Local::now(),
+      state: (Instant::now(), false),
+      active: Duration::ZERO,
+      idle: Duration::ZERO,
+      transitions: 0,
+      errors: BTreeMap::from([(source, 0)]),
+      hint: None,
+    }
+  }
+
+  /// Account for a change to the `idle` state.
+  pub fn transition(&mut self, idle: bool) {
+    self.add_elapsed();
+    self.state.1 = idle;
+    self.transitions += 1;
+  }
+
+  /// Count a failed poll of `source`.
+  pub fn error(&mut self, source: String) {
+    *self.errors.entry(source).or_default() += 1;
+  }
+
+  /// Make sure errors of `source` are listed even if there are none.
+  pub fn add_source(&mut self, source: String) {
+    self.errors.entry(source).or_default();
+  }
+
+  pub fn hint(&mut self, idle: bool) {
+    self.hint = Some(idle);
+  }
+
+  /// The summary of the run up to now.
+  pub fn summary(&mut self) -> Summary {
+    self.add_elapsed();
+    let format = |time: DateTime<Local>| {
+      time.to_rfc3339_opts(SecondsFormat::Millis, false)
+    };
+    Summary {
+      start: format(self.started),
+      stop: format(Local::now()),
+      active_ms: self.active.as_millis() as u64,
+      idle_ms: self.idle.as_millis() as u64,
+      transitions: self.transitions,
+      errors: self.errors.clone(),
+      final_hint: self.hint,
+    }
+  }
+
+  fn add_elapsed(&mut self) {
+    let (since, idle) = self.state;
+    let elapsed = since.elapsed();
+    if idle {
+      self.idle += elapsed;
+    } else {
+      self.active += elapsed;
+    }
+    self.state.0 = Instant::now();
+  }
+}
+
+impl Summary {
+  /// Write the summary as JSON to `path`, or to stdout for `-`.
+  pub fn write(&self, path: &Path) -> Result<()> {
+    let json = serde_json::to_string(self)? + "\n";
+    if path == Path::new("-") {
+      print!("{json}");
+      return Ok(());
+    }
+    fs::write(path, json)
+      .with_context(|| format!("Failed to write summary to {}", path.display()))
+  }
+}
@@ -0,0 +1,249 @@
+use anyhow::{anyhow, Context, Result};
+use std::time::Duration;
+use tokio::{
+  sync::{mpsc, oneshot},
+  time::{timeout_at, Instant},
+};
+use tracing::{debug, error, info, warn};
+
+use crate::{
+  backoff::{self, RetryConfig},
+  exit::Fatal,
+  x11::{ScreensaverSource, XOptions},
+};
+
+/// Where a display task sends the reading it was asked for.
+type Reply = oneshot::Sender<Result<Duration>>;
+
+/// The supervisor's end of a display task.
+struct DisplayHandle {
+  name: String,
+  readings: mpsc::Sender<Reply>,
+  /// DPMS levels to force
+  dpms: mpsc::Sender<bool>,
+  /// The display became unavailable and could not be reconnected
+  failed: bool,
+}
+
+impl DisplayHandle {
+  /// Hand `source`, connected to `display`, over to a task of its own.
+  fn spawn(
+    source: ScreensaverSource,
+    display: Option<String>,
+    retry: RetryConfig,
+    x_options: XOptions,
+  ) -> Self {
+    let name = source.display().unwrap_or("default display").to_string();
+    // A pending request is answered by the next reading anyway
+    let (readings_tx, readings_rx) = mpsc::channel(1);
+    let (dpms_tx, dpms_rx) = mpsc::channel(1);
+    let task = DisplayTask {
+      name: name.clone(),
+      display,
+      retry,
+      x_options,
+      readings: readings_rx,
+      dpms: dpms_rx,
+    };
+    tokio::spawn(task.run(source));
+    Self {
+      name,
+      readings: readings_tx,
+      dpms: dpms_tx,
+      failed: false,
+    }
+  }
+}
+
+/// Upper bound of how long a poll waits for the displays, so that a slow
+/// display doesn't hold up signals and shutdown behind it.
+const MAX_REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Watches any number of X displays, each from its own task that takes
+/// care of reconnecting. Every poll asks each display for a reading of its
+/// own, and the idle time of the session is that of the most recently used
+/// display.
+pub struct DisplaySupervisor {
+  displays: Vec<DisplayHandle>,
+  /// How long a poll waits for the displays to answer
+  reply_timeout: Duration,
+}
+
+impl DisplaySupervisor {
+  /// Connect to `displays` (`None` being the default display). Displays
+  /// that take longer than a quarter of `period`, at most
+  /// [`MAX_REPLY_TIMEOUT`], to answer a poll, e.g. because they are
+  /// reconnecting, are left out of it.
+  pub async fn new(
+    displays: &[Option<String>],
+    period: Duration,
+    retry: RetryConfig,
+    x_options: XOptions,
+  ) -> Result<Self> {
+    let mut handles = vec![];
+    for display in displays {
+      let source = ScreensaverSource::connect(display.as_deref(), x_options)
+        .await
+        .context(Fatal::XUnavailable)?;
+      handles.push(DisplayHandle::spawn(
+        source,
+        display.clone(),
+        retry,
+        x_options,
+      ));
+    }
+
+    Ok(Self {
+      displays: handles,
+      reply_timeout: (period / 4).min(MAX_REPLY_TIMEOUT),
+    })
+  }
+
+  pub async fn idle_time(&mut self) -> Result<Option<Duration>> {
+    // Ask every display at once, then collect the replies
+    let mut pending = vec![];
+    for (index, display) in self.displays.iter_mut().enumerate() {
+      if display.failed {
+        continue;
+      }
+      let (tx, rx) = oneshot::channel();
+      match display.readings.try_send(tx) {
+        Ok(()) => pending.push((index, rx)),
+        Err(mpsc::error::TrySendError::Full(_)) => {
+          let name = &display.name;
+          debug!("X display {name} is still busy");
+        }
+        Err(mpsc::error::TrySendError::Closed(_)) => display.failed = true,
+      }
+    }
+
+    let deadline = Instant::now() + self.reply_timeout;
+    let mut idle_time: Option<Duration> = None;
+    for (index, rx) in pending {
+      let display = &mut self.displays[index];
+      let name = &display.name;
+      match timeout_at(deadline, rx).await {
+        Ok(Ok(Ok(idle))) => {
+          idle_time = Some(idle_time.map_or(idle, |min| min.min(idle)));
+        }
+        Ok(Ok(Err(e))) => {
+          warn!("No idle reading from X display {name}: {e:#}");
+        }
+        // The task gave up on the display
+        Ok(Err(_)) => display.failed = true,
+        Err(_) => debug!("X display {name} did not answer in time"),
+      }
+    }
+
+    if idle_time.is_none() {
+      if self.displays.iter().all(|display| display.failed) {
+        return Err(
+          anyhow!("All X displays are unavailable")
+            .context(Fatal::XUnavailable),
+        );
+      }
+      warn!("Skipping poll, no idle reading from any X display");
+    }
+
+    Ok(idle_time)
+  }
+
+  /// Force the screens of all displays on or off, see [`XOptions::dpms`].
+  pub fn force_dpms(&self, on: bool) {
+    for display in &self.displays {
+      // A pending level is superseded anyway
+      let _ = display.dpms.try_send(on);
+    }
+  }
+}
+
+/// Reads the idle time of one display on request and forwards DPMS levels
+/// to it.
+struct DisplayTask {
+  name: String,
+  display: Option<String>,
+  retry: RetryConfig,
+  x_options: XOptions,
+  readings: mpsc::Receiver<Reply>,
+  dpms: mpsc::Receiver<bool>,
+}
+
+impl DisplayTask {
+  async fn run(mut self, mut source: ScreensaverSource) {
+    loop {
+      tokio::select! {
+        Some(reply) = self.readings.recv() => {
+          let idle = match source.idle_time().await {
+            Ok(idle) => Ok(idle),
+            Err(e) => {
+              warn!("Lost X display {}: {e:#}", self.name);
+              match self.reconnect().await {
+                Ok(new_source) => {
+                  info!("Reconnected to X display {}", self.name);
+                  source = new_source;
+                  source.idle_time().await
+                }
+                Err(e) => {
+                  // Dropping the reply tells the supervisor
+                  error!("Giving up on X display {}: {e:#}", self.name);
+                  return;
+                }
+              }
+            }
+          };
+          let _ = reply.send(idle);
+        }
+        Some(on) = self.dpms.recv() => {
+          if let Err(e) = source.force_dpms(on).await {
+            warn!(
+              "Failed to force the screen of {} on or off: {e:#}",
+              self.name
+            );
+          }
+        }
+        // The supervisor is gone
+        else => return,
+      }
+    }
+  }
+
+  async fn reconnect(&self) -> Result<ScreensaverSource> {
+    backoff::retry(self.retry, "Reconnecting to X display", || {
+      ScreensaverSource::connect(self.display.as_deref(), self.x_options)
+    })
+    .await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn every_poll_reads_the_display() {
+    let mut readings = 0;
+    let source = ScreensaverSource::idle_stub(move || {
+      readings += 1;
+      Duration::from_secs(readings)
+    });
+    let retry = RetryConfig {
+      initial_delay: Duration::ZERO,
+      max_delay: Duration::ZERO,
+      multiplier: 1.0,
+      max_attempts: 1,
+    };
+    let display =
+      DisplayHandle::spawn(source, None, retry, XOptions::default());
+    let mut supervisor = DisplaySupervisor {
+      displays: vec![display],
+      reply_timeout: Duration::from_secs(1),
+    };
+
+    // Polls in quick succession, as with a refresh right after a regular
+    // poll, each get a reading of their own
+    for expected in 1..=3 {
+      let idle = supervisor.idle_time().await.unwrap();
+      assert_eq!(idle, Some(Duration::from_secs(expected)));
+    }
+  }
+}
@@ -0,0 +1,97 @@
+use anyhow::{Context, Result};
+use futures_util::StreamExt;
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::warn;
+use zbus::{zvariant::OwnedFd, Connection};
+
+use crate::{
+  hooks::{self, Env},
+  logind::{self, Login1ManagerProxy},
+  monitor::Status,
+};
+
+/// Commands to run around system suspend.
+#[derive(Clone, Debug, Default)]
+pub struct SleepHooks {
+  pub on_suspend: Option<String>,
+  pub on_resume: Option<String>,
+  /// Delay suspend while the suspend hook runs, for at most this long
+  pub suspend_timeout: Option<Duration>,
+  /// Fail rather than wait for authorization, see [`logind::inhibit`]
+  pub non_interactive: bool,
+}
+
+/// Watch logind's PrepareForSleep signal and run the hooks accordingly,
+/// telling them the latest `status` of the idle source `backend`.
+pub async fn spawn(
+  conn: &Connection,
+  hooks: SleepHooks,
+  status: watch::Receiver<Status>,
+  backend: String,
+) -> Result<()> {
+  let manager = Login1ManagerProxy::new(conn).await?;
+  let mut signals = manager
+    .receive_prepare_for_sleep()
+    .await
+    .context("Failed to subscribe to PrepareForSleep")?;
+
+  let mut lock = take_lock(&manager, &hooks).await;
+
+  tokio::spawn(async move {
+    while let Some(signal) = signals.next().await {
+      let Ok(args) = signal.args() else {
+        continue;
+      };
+
+      let env = Env {
+        status: *status.borrow(),
+        backend: backend.clone(),
+      };
+      if args.start {
+        let held = lock.take();
+        if let Some(cmd) = hooks.on_suspend.clone() {
+          // The hook runs on its own so that a resume isn't missed while it
+          // hangs. It is bounded by the timeout whenever the lock is held.
+          let limit = hooks.suspend_timeout;
+          tokio::spawn(async move {
+            hooks::run("suspend", &cmd, limit, &env).await;
+            // Let the system go to sleep
+            drop(held);
+          });
+        }
+      } else {
+        lock = take_lock(&manager, &hooks).await;
+        if let Some(cmd) = hooks.on_resume.clone() {
+          tokio::spawn(
+            async move { hooks::run("resume", &cmd, None, &env).await },
+          );
+        }
+      }
+    }
+    drop(lock);
+  });
+
+  Ok(())
+}
+
+/// Take a delay inhibitor lock if the suspend hook should hold up suspend.
+async fn take_lock(
+  manager: &Login1ManagerProxy<'_>,
+  hooks: &SleepHooks,
+) -> Option<OwnedFd> {
+  if hooks.on_suspend.is_none() || hooks.suspend_timeout.is_none() {
+    return None;
+  }
+
+  logind::inhibit(
+    manager,
+    "sleep",
+    "Running suspend hook",
+    "delay",
+    hooks.non_interactive,
+  )
+  .await
+  .inspect_err(|e| warn!("{e:#}"))
+  .ok()
+}
@@ -0,0 +1,153 @@
+use chrono::{DateTime, Local};
+use std::{
+  fmt::Write as _,
+  io::{self, IsTerminal, Write},
+  time::{Duration, SystemTime},
+};
+use tokio::{
+  sync::watch,
+  task::JoinHandle,
+  time::{interval, MissedTickBehavior},
+};
+use tracing::info;
+
+use crate::{
+  logging::LogOutput,
+  monitor::{format_duration, Status},
+};
+
+const BAR_WIDTH: usize = 40;
+
+/// Number of held back log lines shown below the status.
+const LOG_LINES: usize = 8;
+
+/// A live view of the monitor's status on the terminal, drawn on the
+/// alternate screen so that the scrollback is left alone. Dropping it
+/// restores the terminal and the log output, even when the monitor fails.
+pub struct StatusView {
+  task: JoinHandle<()>,
+  log: LogOutput,
+}
+
+impl StatusView {
+  /// Start drawing `status`, unless stdout is not a terminal, in which
+  /// case logging carries on as usual.
+  pub fn start(
+    status: watch::Receiver<Status>,
+    log: LogOutput,
+  ) -> Option<Self> {
+    if !io::stdout().is_terminal() {
+      info!("Not a terminal, logging instead of showing the status view");
+      return None;
+    }
+
+    log.hold();
+    print!("\x1b[?1049h\x1b[?25l");
+    let task = tokio::spawn(draw_loop(status, log.clone()));
+    Some(Self { task, log })
+  }
+}
+
+impl Drop for StatusView {
+  fn drop(&mut self) {
+    self.task.abort();
+    print!("\x1b[?25h\x1b[?1049l");
+    let _ = io::stdout().flush();
+    self.log.release();
+  }
+}
+
+/// Redraw on every status update, and every second so that the ages stay
+/// current in between.
+async fn draw_loop(mut status: watch::Receiver<Status>, log: LogOutput) {
+  let mut ticker = interval(Duration::from_secs(1));
+  ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+  loop {
+    let screen = render(&status.borrow_and_update(), &log);
+    {
+      let mut stdout = io::stdout().lock();
+      let _ = write!(stdout, "\x1b[H\x1b[2J{screen}");
+      let _ = stdout.flush();
+    }
+
+    tokio::select! {
+      changed = status.changed() => {
+        if changed.is_err() {
+          return;
+        }
+      }
+      _ = ticker.tick() => {}
+    }
+  }
+}
+
+fn render(status: &Status, log: &LogOutput) -> String {
+  let mut screen = String::new();
+  let now = Local::now().format("%H:%M:%S");
+  let _ = writeln!(screen, "x11-idle-sync status{now:>46}\n");
+
+  let state = match (status.last_success, status.idle) {
+    (None, _) => "unknown",
+    (Some(_), true) => "idle",
+    (Some(_), false) => "active",
+  };
+  let reason = status
+    .reason
+    .map(|reason| format!(" ({reason})"))
+    .unwrap_or_default();
+  let _ = writeln!(screen, "State       {state}{reason}");
+
+  let ratio = if status.threshold.is_zero() {
+    1.0
+  } else {
+    status.idle_time.as_secs_f64() / status.threshold.as_secs_f64()
+  };
+  let filled = ((ratio.min(1.0) * BAR_WIDTH as f64) as usize).min(BAR_WIDTH);
+  let _ = writeln!(
+    screen,
+    "Idle        [{}{}] {} / {}",
+    "#".repeat(filled),
+    ".".repeat(BAR_WIDTH - filled),
+    format_duration(status.idle_time),
+    format_duration(status.threshold)
+  );
+
+  let _ = writeln!(
+    screen,
+    "Transition  {}",
+    status
+      .last_transition
+      .map_or("none yet".to_string(), format_time)
+  );
+  if let Some(since) = status.idle_since {
+    let _ = writeln!(screen, "Last input  {}", format_time(since));
+  }
+
+  let poll = match (status.last_success, status.poll_latency) {
+    (Some(at), Some(latency)) => format!(
+      "{} {:.1}ms, {} ago",
+      if status.last_poll_failed {
+        "failing, last good reading took"
+      } else {
+        "reading took"
+      },
+      latency.as_secs_f64() * 1000.0,
+      format_duration(at.elapsed())
+    ),
+    _ => "no reading yet".to_string(),
+  };
+  let _ = writeln!(screen, "Poll        {poll}");
+
+  let lines = log.held_lines(LOG_LINES);
+  if !lines.is_empty() {
+    let _ = writeln!(screen, "\nLog");
+    for line in lines {
+      let _ = writeln!(screen, "  {line}");
+    }
+  }
+  screen
+}
+
+fn format_time(time: SystemTime) -> String {
+  DateTime::<Local>::from(time).format("%H:%M:%S").to_string()
+}
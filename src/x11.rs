@@ -0,0 +1,604 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Serialize;
+use std::{
+  sync::mpsc,
+  thread,
+  time::{Duration, Instant},
+};
+use tokio::{sync::oneshot, time::timeout};
+use tracing::{info, warn};
+use xcb::{
+  dpms, randr, screensaver,
+  x::{self, Drawable, Window},
+  xtest, BaseEvent, Connection, Extension,
+};
+
+enum Request {
+  IdleTime(oneshot::Sender<Result<Duration>>),
+  QueryInfo(oneshot::Sender<Result<ScreensaverInfo>>),
+  /// Force the screen on or off
+  Dpms(bool, oneshot::Sender<Result<()>>),
+  SimulateInput(oneshot::Sender<Result<Option<InputCheck>>>),
+}
+
+/// Oldest MIT-SCREEN-SAVER version known to report the idle time reliably.
+const MIN_SCREENSAVER_VERSION: (u16, u16) = (1, 1);
+
+/// Idle time to wait for before simulating input, so that a reset shows.
+const MIN_IDLE_BEFORE_INPUT: Duration = Duration::from_secs(1);
+
+/// The MIT-SCREEN-SAVER `QueryInfo` reply, as is.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct ScreensaverInfo {
+  /// `off`, `on`, `cycle` or `disabled`
+  pub state: &'static str,
+  /// `blanked`, `internal` or `external`
+  pub kind: &'static str,
+  pub ms_since_user_input: u32,
+  /// Time until the screensaver activates, or since it did while it is on
+  pub ms_until_server: u32,
+}
+
+/// The idle counter around a simulated key press.
+#[derive(Clone, Copy, Debug)]
+pub struct InputCheck {
+  pub keycode: u8,
+  pub before: Duration,
+  pub after: Duration,
+}
+
+impl InputCheck {
+  pub fn reset(&self) -> bool {
+    self.after < self.before
+  }
+}
+
+/// How to talk to X displays.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct XOptions {
+  /// Count pointer motion as activity
+  pub watch_pointer: bool,
+  /// Refuse servers with a too old MIT-SCREEN-SAVER extension
+  pub strict_extension: bool,
+  /// Check that the idle counter actually advances after connecting
+  pub verify_counter: bool,
+  /// Refuse servers whose idle counter is stuck
+  pub strict_counter: bool,
+  /// Force the screen off with DPMS while the user is idle
+  pub dpms: bool,
+  /// Time allowed for connecting to a display over TCP
+  pub connect_timeout: Duration,
+  /// Time allowed for a display over TCP to answer a request
+  pub reply_timeout: Duration,
+}
+
+/// Handle to an X connection that lives on its own OS thread.
+///
+/// The xcb `Connection` never leaves that thread; idle readings are
+/// requested over a channel instead, so the handle can be cloned and used
+/// from any task. The thread exits once every handle has been dropped.
+#[derive(Clone)]
+pub struct ScreensaverSource {
+  requests: mpsc::Sender<Request>,
+  display: Option<String>,
+  /// Applies to displays over TCP, local ones answer promptly or not at all
+  reply_timeout: Option<Duration>,
+}
+
+impl ScreensaverSource {
+  /// Connect to X display `display`, or to `$DISPLAY` if `None`. A host
+  /// name in `display` is resolved anew on every call, so reconnecting
+  /// follows DNS changes.
+  pub async fn connect(
+    display: Option<&str>,
+    options: XOptions,
+  ) -> Result<Self> {
+    let (ready_tx, ready_rx) = oneshot::channel();
+    let (requests, rx) = mpsc::channel();
+    let display = display
+      .map(str::to_string)
+      .or_else(|| std::env::var("DISPLAY").ok());
+
+    let thread_display = display.clone();
+    thread::Builder::new()
+      .name(format!("x11 {}", display.as_deref().unwrap_or("default")))
+      .spawn(move || {
+        match XConnection::new(thread_display.as_deref(), options) {
+          Ok(conn) => {
+            let _ = ready_tx.send(Ok(()));
+            conn.serve(rx);
+          }
+          Err(e) => {
+            let _ = ready_tx.send(Err(e));
+          }
+        }
+      })
+      .context("Failed to spawn X11 thread")?;
+
+    // The thread stays blocked if the connection attempt hangs, but gives
+    // up by itself once it gets through and finds no handle
+    let remote = display.as_deref().is_some_and(is_tcp);
+    let ready = async { ready_rx.await.context("X11 thread exited")? };
+    if remote {
+      timeout(options.connect_timeout, ready)
+        .await
+        .with_context(|| {
+          format!(
+            "Timed out connecting to X display {} after {}s",
+            display.as_deref().unwrap_or_default(),
+            options.connect_timeout.as_secs()
+          )
+        })??;
+    } else {
+      ready.await?;
+    }
+    Ok(Self {
+      requests,
+      display,
+      reply_timeout: remote.then_some(options.reply_timeout),
+    })
+  }
+
+  pub fn display(&self) -> Option<&str> {
+    self.display.as_deref()
+  }
+
+  pub async fn idle_time(&self) -> Result<Duration> {
+    self.request(Request::IdleTime).await
+  }
+
+  /// The raw screensaver state, without pointer watching.
+  pub async fn query_info(&self) -> Result<ScreensaverInfo> {
+    self.request(Request::QueryInfo).await
+  }
+
+  /// Force the screen on or off, if enabled with [`XOptions::dpms`].
+  pub async fn force_dpms(&self, on: bool) -> Result<()> {
+    self.request(|tx| Request::Dpms(on, tx)).await
+  }
+
+  /// Press and release an unmapped key with XTEST and read the idle
+  /// counter around it. `None` if the server lacks XTEST.
+  pub async fn simulate_input(&self) -> Result<Option<InputCheck>> {
+    self.request(Request::SimulateInput).await
+  }
+
+  /// Send a request to the X11 thread and wait for its reply. A display
+  /// over TCP that doesn't answer in time counts as lost, so that the
+  /// caller reconnects rather than waiting on a dead link.
+  async fn request<T>(
+    &self,
+    request: impl FnOnce(oneshot::Sender<Result<T>>) -> Request,
+  ) -> Result<T> {
+    let (tx, rx) = oneshot::channel();
+    self
+      .requests
+      .send(request(tx))
+      .map_err(|_| anyhow!("X11 thread exited"))?;
+    let reply = async { rx.await.context("X11 thread exited")? };
+    match self.reply_timeout {
+      Some(limit) => timeout(limit, reply).await.with_context(|| {
+        format!("X server did not reply within {}s", limit.as_secs())
+      })?,
+      None => reply.await,
+    }
+  }
+}
+
+/// Whether `display` is reached over TCP, like `host:0` or `tcp/host:0`,
+/// rather than over a local socket like `:0` or `unix:0`.
+fn is_tcp(display: &str) -> bool {
+  let Some((host, _)) = display.rsplit_once(':') else {
+    // A socket path
+    return false;
+  };
+  match host.split_once('/') {
+    Some((protocol, _)) => protocol != "unix",
+    None => !host.is_empty() && host != "unix",
+  }
+}
+
+struct XConnection {
+  conn: Connection,
+  root: Window,
+  /// Last pointer position and when it moved there, if watching the
+  /// pointer
+  pointer: Option<Pointer>,
+  /// Whether to force the screen off while idle, if the server can
+  dpms: bool,
+}
+
+#[derive(Default)]
+struct Pointer {
+  position: Option<(Window, i16, i16)>,
+  /// Unknown until the pointer has been seen moving, as it may have been
+  /// resting long before we connected
+  since: Option<Instant>,
+}
+
+impl XConnection {
+  fn new(display: Option<&str>, options: XOptions) -> Result<Self> {
+    let (conn, screen_num) = Connection::connect_with_extensions(
+      display,
+      &[],
+      &[
+        Extension::ScreenSaver,
+        Extension::RandR,
+        Extension::Dpms,
+        Extension::Test,
+      ],
+    )
+    .context("Failed to connect to X11 server")?;
+    check_screensaver_version(&conn, options.strict_extension)?;
+    let setup = conn.get_setup();
+    let screen = setup
+      .roots()
+      .nth(screen_num as usize)
+      .context("Failed to get X11 screen")?;
+    let root = screen.root();
+
+    // Get notified when the screen layout changes so that we can pick up a
+    // new root window
+    if conn.active_extensions().any(|ext| ext == Extension::RandR) {
+      conn
+        .send_and_check_request(&randr::SelectInput {
+          window: root,
+          enable: randr::NotifyMask::SCREEN_CHANGE,
+        })
+        .context("Failed to subscribe to RandR screen changes")?;
+    }
+
+    let mut connection = Self {
+      conn,
+      root,
+      pointer: options.watch_pointer.then(Pointer::default),
+      dpms: false,
+    };
+    if options.verify_counter {
+      connection.verify_counter(options.strict_counter)?;
+    }
+    if options.dpms {
+      connection.dpms = connection.dpms_capable()?;
+    }
+    Ok(connection)
+  }
+
+  /// Make sure the idle counter advances, as it is stuck on some virtual
+  /// X servers.
+  fn verify_counter(&self, strict: bool) -> Result<()> {
+    let first = self.get_idle_duration()?;
+    thread::sleep(Duration::from_secs(1));
+    let second = self.get_idle_duration()?;
+
+    if second == first {
+      let message = format!(
+        "X idle counter is stuck at {}ms, idle detection won't work",
+        first.as_millis()
+      );
+      if strict {
+        bail!(message);
+      }
+      warn!("{message}");
+    } else if second < first {
+      info!("Got input while verifying the X idle counter, assuming it works");
+    } else {
+      info!("X idle counter is advancing");
+    }
+    Ok(())
+  }
+
+  fn dpms_capable(&self) -> Result<bool> {
+    if !self
+      .conn
+      .active_extensions()
+      .any(|ext| ext == Extension::Dpms)
+    {
+      warn!("X server lacks the DPMS extension, not forcing the screen off");
+      return Ok(false);
+    }
+    let cookie = self.conn.send_request(&dpms::Capable {});
+    let reply = self
+      .conn
+      .wait_for_reply(cookie)
+      .context("Failed to query DPMS")?;
+    if !reply.capable() {
+      warn!("X server can't control the monitor, not forcing the screen off");
+    }
+    Ok(reply.capable())
+  }
+
+  /// Answer requests until every handle is gone.
+  fn serve(mut self, requests: mpsc::Receiver<Request>) {
+    while let Ok(request) = requests.recv() {
+      match request {
+        Request::IdleTime(reply) => {
+          let _ = reply.send(self.idle_time());
+        }
+        Request::QueryInfo(reply) => {
+          let _ =
+            reply.send(self.process_events().and_then(|()| self.query_info()));
+        }
+        Request::Dpms(on, reply) => {
+          let _ = reply.send(self.force_dpms(on));
+        }
+        Request::SimulateInput(reply) => {
+          let _ = reply.send(self.simulate_input());
+        }
+      }
+    }
+  }
+
+  fn force_dpms(&self, on: bool) -> Result<()> {
+    if !self.dpms {
+      return Ok(());
+    }
+    // Forcing a level fails while DPMS is disabled, e.g. with `xset -dpms`
+    let cookie = self.conn.send_request(&dpms::Info {});
+    let info = self
+      .conn
+      .wait_for_reply(cookie)
+      .context("Failed to query DPMS state")?;
+    if !info.state() {
+      warn!("DPMS is disabled on the X server, not forcing the screen off");
+      return Ok(());
+    }
+
+    let power_level = if on {
+      dpms::DpmsMode::On
+    } else {
+      dpms::DpmsMode::Off
+    };
+    self
+      .conn
+      .send_and_check_request(&dpms::ForceLevel { power_level })
+      .context("Failed to force DPMS level")
+  }
+
+  fn simulate_input(&self) -> Result<Option<InputCheck>> {
+    if !self
+      .conn
+      .active_extensions()
+      .any(|ext| ext == Extension::Test)
+    {
+      return Ok(None);
+    }
+    let keycode = self.unmapped_keycode()?;
+
+    // Leave the counter some room to go back, in case of recent input
+    let mut before = self.get_idle_duration()?;
+    if before < MIN_IDLE_BEFORE_INPUT {
+      thread::sleep(MIN_IDLE_BEFORE_INPUT - before);
+      before = self.get_idle_duration()?;
+    }
+    for event in [x::KeyPressEvent::NUMBER, x::KeyReleaseEvent::NUMBER] {
+      self
+        .conn
+        .send_and_check_request(&xtest::FakeInput {
+          r#type: event as u8,
+          detail: keycode,
+          time: x::CURRENT_TIME,
+          root: self.root,
+          root_x: 0,
+          root_y: 0,
+          deviceid: 0,
+        })
+        .context("Failed to simulate a key press")?;
+    }
+    let after = self.get_idle_duration()?;
+    Ok(Some(InputCheck {
+      keycode,
+      before,
+      after,
+    }))
+  }
+
+  /// A key code without any key symbols, which no client acts upon.
+  fn unmapped_keycode(&self) -> Result<u8> {
+    let setup = self.conn.get_setup();
+    let (min, max) = (setup.min_keycode(), setup.max_keycode());
+    let cookie = self.conn.send_request(&x::GetKeyboardMapping {
+      first_keycode: min,
+      count: max - min + 1,
+    });
+    let reply = self
+      .conn
+      .wait_for_reply(cookie)
+      .context("Failed to get keyboard mapping")?;
+    let per_keycode = reply.keysyms_per_keycode().max(1) as usize;
+    reply
+      .keysyms()
+      .chunks(per_keycode)
+      .position(|keysyms| keysyms.iter().all(|&sym| sym == x::NO_SYMBOL))
+      .map(|index| min + index as u8)
+      .context("Every key code is mapped, no key to press safely")
+  }
+
+  fn idle_time(&mut self) -> Result<Duration> {
+    self.process_events()?;
+    let idle = self.get_idle_duration()?;
+    match self.pointer_idle_duration()? {
+      Some(pointer_idle) => Ok(idle.min(pointer_idle)),
+      None => Ok(idle),
+    }
+  }
+
+  /// Time since the pointer last moved, for remote desktop setups that warp
+  /// the pointer without generating input events. `None` until it moved
+  /// while connected.
+  fn pointer_idle_duration(&mut self) -> Result<Option<Duration>> {
+    let Some(pointer) = &mut self.pointer else {
+      return Ok(None);
+    };
+
+    let cookie = self
+      .conn
+      .send_request(&x::QueryPointer { window: self.root });
+    let reply = self
+      .conn
+      .wait_for_reply(cookie)
+      .context("Failed to query pointer")?;
+    let position = Some((reply.root(), reply.root_x(), reply.root_y()));
+
+    // The first reading only establishes where the pointer is
+    if pointer.position.is_some() && pointer.position != position {
+      pointer.since = Some(Instant::now());
+    }
+    pointer.position = position;
+    Ok(pointer.since.map(|since| since.elapsed()))
+  }
+
+  fn get_idle_duration(&self) -> Result<Duration> {
+    let idle_ms = self.query_info()?.ms_since_user_input;
+    Ok(Duration::from_millis(idle_ms as u64))
+  }
+
+  fn query_info(&self) -> Result<ScreensaverInfo> {
+    let cookie = self.conn.send_request(&screensaver::QueryInfo {
+      drawable: Drawable::Window(self.root),
+    });
+    let reply = self
+      .conn
+      .wait_for_reply(cookie)
+      .context("Failed to get screensaver info")?;
+
+    // The state isn't typed by xcb, so unknown values are possible
+    let state = match reply.state() {
+      0 => "off",
+      1 => "on",
+      2 => "cycle",
+      3 => "disabled",
+      _ => "unknown",
+    };
+    let kind = match reply.kind() {
+      screensaver::Kind::Blanked => "blanked",
+      screensaver::Kind::Internal => "internal",
+      screensaver::Kind::External => "external",
+    };
+    Ok(ScreensaverInfo {
+      state,
+      kind,
+      ms_since_user_input: reply.ms_since_user_input(),
+      ms_until_server: reply.ms_until_server(),
+    })
+  }
+
+  fn process_events(&mut self) -> Result<()> {
+    while let Some(event) = self
+      .conn
+      .poll_for_event()
+      .context("Failed to read X11 event")?
+    {
+      if let xcb::Event::RandR(randr::Event::ScreenChangeNotify(ev)) = event {
+        self.root = ev.root();
+        info!("Screen layout changed, refreshed root window");
+      }
+    }
+    Ok(())
+  }
+}
+
+fn check_screensaver_version(conn: &Connection, strict: bool) -> Result<()> {
+  if !conn
+    .active_extensions()
+    .any(|ext| ext == Extension::ScreenSaver)
+  {
+    bail!("X server lacks the MIT-SCREEN-SAVER extension");
+  }
+
+  let (min_major, min_minor) = MIN_SCREENSAVER_VERSION;
+  let cookie = conn.send_request(&screensaver::QueryVersion {
+    client_major_version: min_major as u8,
+    client_minor_version: min_minor as u8,
+  });
+  let reply = conn
+    .wait_for_reply(cookie)
+    .context("Failed to query MIT-SCREEN-SAVER version")?;
+  let version = (reply.server_major_version(), reply.server_minor_version());
+  info!(
+    "X server supports MIT-SCREEN-SAVER {}.{}",
+    version.0, version.1
+  );
+
+  if version < MIN_SCREENSAVER_VERSION {
+    let message = format!(
+      "MIT-SCREEN-SAVER {}.{} is older than {min_major}.{min_minor}, idle \
+       times may be unreliable",
+      version.0, version.1
+    );
+    if strict {
+      bail!(message);
+    }
+    warn!("{message}");
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  impl ScreensaverSource {
+    /// A source whose requests are answered by `serve` on a thread of its
+    /// own, in place of an X connection.
+    fn stub(
+      serve: impl FnOnce(mpsc::Receiver<Request>) + Send + 'static,
+    ) -> Self {
+      let (requests, rx) = mpsc::channel();
+      thread::spawn(move || serve(rx));
+      Self {
+        requests,
+        display: None,
+        reply_timeout: None,
+      }
+    }
+
+    /// A source answering every idle time request with what `reading`
+    /// returns at the time.
+    pub(crate) fn idle_stub(
+      mut reading: impl FnMut() -> Duration + Send + 'static,
+    ) -> Self {
+      Self::stub(move |rx| {
+        for request in rx {
+          if let Request::IdleTime(reply) = request {
+            let _ = reply.send(Ok(reading()));
+          }
+        }
+      })
+    }
+  }
+
+  #[tokio::test]
+  async fn concurrent_requests_get_their_own_replies() {
+    // Wait for all requests before answering them in reverse, so that every
+    // reply is outstanding while the others are answered
+    let source = ScreensaverSource::stub(|rx| {
+      let replies: Vec<_> = rx
+        .iter()
+        .take(4)
+        .map(|request| match request {
+          Request::IdleTime(reply) => reply,
+          _ => panic!("unexpected request"),
+        })
+        .collect();
+      for (index, reply) in replies.into_iter().enumerate().rev() {
+        let _ = reply.send(Ok(Duration::from_millis(index as u64)));
+      }
+    });
+
+    // Joined futures are polled in order, so request n is sent n-th
+    let (a, b, c, d) = tokio::join!(
+      source.idle_time(),
+      source.idle_time(),
+      source.idle_time(),
+      source.idle_time()
+    );
+    let readings = [a, b, c, d].map(|idle| idle.unwrap().as_millis());
+    assert_eq!(readings, [0, 1, 2, 3]);
+  }
+
+  #[tokio::test]
+  async fn requests_fail_once_thread_exits() {
+    let source = ScreensaverSource::stub(drop);
+    assert!(source.idle_time().await.is_err());
+  }
+}
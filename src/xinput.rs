@@ -0,0 +1,254 @@
+use anyhow::{anyhow, bail, Context, Result};
+use std::{
+  convert::Infallible,
+  sync::{Arc, Mutex, MutexGuard, PoisonError},
+  time::{Duration, Instant},
+};
+use tokio::{
+  io::unix::AsyncFd,
+  task::{self, JoinSet},
+};
+use tracing::{debug, error, info, warn};
+use xcb::{screensaver, x::Drawable, xinput, Connection, Extension};
+
+use crate::{
+  backoff::{self, RetryConfig},
+  exit::Fatal,
+};
+
+/// Oldest XInput version with raw events.
+const MIN_XINPUT_VERSION: (u16, u16) = (2, 0);
+
+/// Takes the idle time from XInput2 raw input events instead of polling
+/// the screensaver's counter, which is coarse on some drivers. Every
+/// display is watched by a task of its own, which reconnects when the
+/// display goes away and stops when the source is dropped.
+pub struct XInputSource {
+  shared: Arc<Shared>,
+  _watchers: JoinSet<()>,
+}
+
+struct Shared {
+  last_event: Mutex<Instant>,
+  /// Why a display stopped delivering events, if one gave up
+  error: Mutex<Option<String>>,
+}
+
+impl XInputSource {
+  /// Listen to the raw events of `displays`, `None` being the default
+  /// display. Lost displays are reconnected to according to `retry`.
+  pub async fn new(
+    displays: &[Option<String>],
+    retry: RetryConfig,
+  ) -> Result<Self> {
+    let mut connections = vec![];
+    for display in displays {
+      connections.push(Display::connect(display.clone()).await?);
+    }
+
+    // Count from the last input before we started listening, as far as
+    // the screensaver can tell
+    let now = Instant::now();
+    let last_event = connections
+      .iter()
+      .filter_map(|display| now.checked_sub(display.idle?))
+      .max()
+      .unwrap_or(now);
+    let shared = Arc::new(Shared {
+      last_event: Mutex::new(last_event),
+      error: Mutex::default(),
+    });
+
+    let mut watchers = JoinSet::new();
+    for display in connections {
+      watchers.spawn(watch(display, retry, shared.clone()));
+    }
+    Ok(Self {
+      shared,
+      _watchers: watchers,
+    })
+  }
+
+  /// The time since the last raw input event on any display.
+  pub fn idle_time(&self) -> Result<Duration> {
+    if let Some(error) = lock(&self.shared.error).as_ref() {
+      return Err(anyhow!("{error}").context(Fatal::XUnavailable));
+    }
+    Ok(lock(&self.shared.last_event).elapsed())
+  }
+}
+
+/// Record the raw events of `display`, reconnecting whenever it is lost
+/// until `retry` gives up.
+async fn watch(mut display: Display, retry: RetryConfig, shared: Arc<Shared>) {
+  loop {
+    let Err(e) = display.read_events(&shared).await;
+    let name = &display.name;
+    warn!("Lost X display {name}: {e:#}");
+
+    let target = display.display.clone();
+    match backoff::retry(retry, "Reconnecting to X display", || {
+      Display::connect(target.clone())
+    })
+    .await
+    {
+      Ok(new_display) => {
+        info!("Reconnected to X display {name}");
+        // Input while we were away still counts
+        if let Some(last_input) = new_display
+          .idle
+          .and_then(|idle| Instant::now().checked_sub(idle))
+        {
+          let mut last_event = lock(&shared.last_event);
+          *last_event = (*last_event).max(last_input);
+        }
+        display = new_display;
+      }
+      Err(e) => {
+        error!("Giving up on X display {name}: {e:#}");
+        *lock(&shared.error) = Some(format!("Lost X display {name}: {e:#}"));
+        return;
+      }
+    }
+  }
+}
+
+/// A display whose raw input events are selected.
+struct Display {
+  /// As passed to `--display`, for reconnecting
+  display: Option<String>,
+  name: String,
+  conn: AsyncFd<Connection>,
+  /// The screensaver's idle time when connecting, if the server has the
+  /// extension
+  idle: Option<Duration>,
+}
+
+impl Display {
+  async fn connect(display: Option<String>) -> Result<Self> {
+    // Connecting blocks until the server answers
+    let (conn, name, idle) = task::spawn_blocking({
+      let display = display.clone();
+      move || connect(display.as_deref())
+    })
+    .await
+    .context("Failed to connect to X11 server")??;
+    let conn = AsyncFd::new(conn).context("Failed to watch X connection")?;
+    Ok(Self {
+      display,
+      name,
+      conn,
+      idle,
+    })
+  }
+
+  /// Record raw input events until the connection fails.
+  async fn read_events(&self, shared: &Shared) -> Result<Infallible> {
+    loop {
+      // xcb may have queued events while reading replies, so drain before
+      // waiting for the socket
+      loop {
+        match self.conn.get_ref().poll_for_event() {
+          Ok(Some(xcb::Event::Input(
+            xinput::Event::RawMotion(_)
+            | xinput::Event::RawKeyPress(_)
+            | xinput::Event::RawButtonPress(_),
+          ))) => *lock(&shared.last_event) = Instant::now(),
+          Ok(Some(_)) => {}
+          Ok(None) => break,
+          Err(xcb::Error::Protocol(e)) => {
+            let name = &self.name;
+            debug!("X error on {name}: {e:?}");
+          }
+          Err(e) => return Err(e).context("Failed to read X11 event"),
+        }
+      }
+      self
+        .conn
+        .readable()
+        .await
+        .context("Failed to wait for X11 events")?
+        .clear_ready();
+    }
+  }
+}
+
+/// Connect to `display` and select its raw input events. Returns the
+/// connection, the display's name and the screensaver's idle time, if the
+/// server has the extension.
+fn connect(
+  display: Option<&str>,
+) -> Result<(Connection, String, Option<Duration>)> {
+  let (conn, screen_num) = Connection::connect_with_extensions(
+    display,
+    &[],
+    &[Extension::Input, Extension::ScreenSaver],
+  )
+  .context("Failed to connect to X11 server")
+  .context(Fatal::XUnavailable)?;
+  let name = display
+    .map(str::to_string)
+    .or_else(|| std::env::var("DISPLAY").ok())
+    .unwrap_or_else(|| "default display".to_string());
+
+  if !conn.active_extensions().any(|ext| ext == Extension::Input) {
+    bail!("X server {name} lacks the XInput extension");
+  }
+  let (major, minor) = MIN_XINPUT_VERSION;
+  let cookie = conn.send_request(&xinput::XiQueryVersion {
+    major_version: major,
+    minor_version: minor,
+  });
+  let reply = conn
+    .wait_for_reply(cookie)
+    .context("Failed to query XInput version")?;
+  let version = (reply.major_version(), reply.minor_version());
+  if version < MIN_XINPUT_VERSION {
+    bail!(
+      "X server {name} supports XInput {}.{}, raw events need {major}.{minor}",
+      version.0,
+      version.1
+    );
+  }
+
+  let root = conn
+    .get_setup()
+    .roots()
+    .nth(screen_num as usize)
+    .context("Failed to get X11 screen")?
+    .root();
+  // Raw events are only delivered to the root window
+  let mask = xinput::XiEventMask::RAW_MOTION
+    | xinput::XiEventMask::RAW_KEY_PRESS
+    | xinput::XiEventMask::RAW_BUTTON_PRESS;
+  conn
+    .send_and_check_request(&xinput::XiSelectEvents {
+      window: root,
+      masks: &[xinput::EventMaskBuf::new(
+        xinput::Device::AllMaster,
+        &[mask],
+      )],
+    })
+    .context("Failed to select XInput raw events")?;
+  debug!("Listening to XInput raw events on {name}");
+
+  let idle = if conn
+    .active_extensions()
+    .any(|ext| ext == Extension::ScreenSaver)
+  {
+    let cookie = conn.send_request(&screensaver::QueryInfo {
+      drawable: Drawable::Window(root),
+    });
+    conn
+      .wait_for_reply(cookie)
+      .ok()
+      .map(|reply| Duration::from_millis(reply.ms_since_user_input() as u64))
+  } else {
+    None
+  };
+  Ok((conn, name, idle))
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+  mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
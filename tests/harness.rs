@@ -0,0 +1,196 @@
+//! End-to-end test: serves a mock logind session on a private bus, runs
+//! x11-idle-sync against it with a scripted idle source and checks the
+//! idle hints it receives. Skipped if `dbus-daemon` is not installed.
+
+use anyhow::{ensure, Context, Result};
+use std::{
+  env, fs,
+  io::{BufRead, BufReader, ErrorKind},
+  path::{Path, PathBuf},
+  process::{Child, Command, Stdio},
+  time::Duration,
+};
+use tokio::{sync::watch, time::timeout};
+use zbus::{connection, interface};
+
+const SESSION_PATH: &str = "/org/freedesktop/login1/session/self";
+
+/// How long any step may take before the test fails.
+const STEP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The part of `org.freedesktop.login1.Session` that x11-idle-sync uses.
+/// Every `SetIdleHint` call is published on `calls`.
+struct Session {
+  calls: watch::Sender<Vec<bool>>,
+}
+
+#[interface(name = "org.freedesktop.login1.Session")]
+impl Session {
+  fn set_idle_hint(&mut self, idle: bool) {
+    self.calls.send_modify(|calls| calls.push(idle));
+  }
+
+  #[zbus(property)]
+  fn idle_hint(&self) -> bool {
+    self.calls.borrow().last().copied().unwrap_or_default()
+  }
+
+  #[zbus(property)]
+  fn display(&self) -> String {
+    String::new()
+  }
+
+  #[zbus(property)]
+  fn id(&self) -> String {
+    "harness".to_string()
+  }
+}
+
+/// A private bus, stopped on drop.
+struct Bus {
+  daemon: Child,
+  address: String,
+}
+
+impl Bus {
+  /// Start a bus in `dir`, or `None` if `dbus-daemon` is missing.
+  fn start(dir: &Path) -> Result<Option<Self>> {
+    let config = dir.join("bus.conf");
+    fs::write(
+      &config,
+      format!(
+        r#"<!DOCTYPE busconfig PUBLIC
+ "-//freedesktop//DTD D-Bus Bus Configuration 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/busconfig.dtd">
+<busconfig>
+  <type>session</type>
+  <listen>unix:path={}</listen>
+  <policy context="default">
+    <allow send_destination="*"/>
+    <allow receive_sender="*"/>
+    <allow own="*"/>
+  </policy>
+</busconfig>"#,
+        dir.join("bus").display()
+      ),
+    )?;
+
+    let daemon = Command::new("dbus-daemon")
+      .arg(format!("--config-file={}", config.display()))
+      .args(["--nofork", "--print-address"])
+      .stdout(Stdio::piped())
+      .spawn();
+    let mut daemon = match daemon {
+      Ok(daemon) => daemon,
+      Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+      Err(e) => return Err(e).context("Failed to run dbus-daemon"),
+    };
+
+    // The address is printed once the bus is ready
+    let stdout = daemon.stdout.take().context("No dbus-daemon stdout")?;
+    let mut address = String::new();
+    BufReader::new(stdout).read_line(&mut address)?;
+    let address = address.trim().to_string();
+    let bus = Self { daemon, address };
+    ensure!(!bus.address.is_empty(), "dbus-daemon printed no address");
+    Ok(Some(bus))
+  }
+}
+
+impl Drop for Bus {
+  fn drop(&mut self) {
+    let _ = self.daemon.kill();
+    let _ = self.daemon.wait();
+  }
+}
+
+/// Report `idle_ms` through the idle file, replacing it atomically so that
+/// no poll sees it half written.
+fn report_idle(path: &Path, idle_ms: u64) -> Result<()> {
+  let temp = path.with_extension("tmp");
+  fs::write(&temp, format!("{idle_ms}\n"))?;
+  fs::rename(&temp, path)?;
+  Ok(())
+}
+
+/// Wait until the latest hint is `idle`.
+async fn wait_for_hint(
+  calls: &mut watch::Receiver<Vec<bool>>,
+  idle: bool,
+) -> Result<()> {
+  timeout(
+    STEP_TIMEOUT,
+    calls.wait_for(|calls| calls.last() == Some(&idle)),
+  )
+  .await
+  .with_context(|| format!("Timed out waiting for SetIdleHint({idle})"))?
+  .context("Mock session is gone")?;
+  Ok(())
+}
+
+#[tokio::test]
+async fn syncs_idle_hint() -> Result<()> {
+  let dir: PathBuf = env::temp_dir()
+    .join(format!("x11-idle-sync-harness-{}", std::process::id()));
+  fs::create_dir_all(&dir)?;
+  let result = run(&dir).await;
+  let _ = fs::remove_dir_all(&dir);
+  result
+}
+
+async fn run(dir: &Path) -> Result<()> {
+  let Some(bus) = Bus::start(dir)? else {
+    eprintln!("dbus-daemon not found, skipping");
+    return Ok(());
+  };
+
+  let (calls_tx, mut calls) = watch::channel(vec![]);
+  let conn = connection::Builder::address(bus.address.as_str())?
+    .name("org.freedesktop.login1")?
+    .serve_at(SESSION_PATH, Session { calls: calls_tx })?
+    .build();
+  let _conn = timeout(STEP_TIMEOUT, conn)
+    .await
+    .context("Timed out connecting to the bus")?
+    .context("Failed to serve mock login1 session")?;
+
+  let idle_file = dir.join("idle");
+  report_idle(&idle_file, 20_000)?;
+  let mut child =
+    tokio::process::Command::new(env!("CARGO_BIN_EXE_x11-idle-sync"))
+      .args(["--idle-threshold", "10", "--fixed-interval", "1", "--quiet"])
+      .arg(format!("--idle-source=fifo:{}", idle_file.display()))
+      .env("DBUS_SYSTEM_BUS_ADDRESS", &bus.address)
+      // Keep clear of the instance guard of a real x11-idle-sync
+      .env_remove("DISPLAY")
+      .stdout(Stdio::null())
+      .kill_on_drop(true)
+      .spawn()
+      .context("Failed to run x11-idle-sync")?;
+
+  wait_for_hint(&mut calls, true).await?;
+  report_idle(&idle_file, 0)?;
+  wait_for_hint(&mut calls, false).await?;
+
+  // A clean shutdown resets the hint once more
+  let before_exit = calls.borrow().len();
+  let pid = child.id().context("x11-idle-sync exited early")?;
+  Command::new("kill").arg(pid.to_string()).status()?;
+  let status = timeout(STEP_TIMEOUT, child.wait())
+    .await
+    .context("x11-idle-sync did not exit")??;
+  ensure!(status.success(), "x11-idle-sync failed: {status}");
+
+  let calls = calls.borrow().clone();
+  ensure!(
+    calls.len() > before_exit && calls.last() == Some(&false),
+    "No idle hint reset on exit, got {calls:?}"
+  );
+  let mut changes = calls.clone();
+  changes.dedup();
+  ensure!(
+    changes == [true, false],
+    "Expected the hint to go idle and then active, got {calls:?}"
+  );
+  Ok(())
+}